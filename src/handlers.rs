@@ -1 +1,3 @@
+pub mod container_handler;
+pub mod metrics_handler;
 pub mod route_handler;
\ No newline at end of file
@@ -0,0 +1,39 @@
+use axum::{extract::{rejection::JsonRejection, Path}, response::IntoResponse, Json};
+use axum_macros::debug_handler;
+use hyper::StatusCode;
+use mongodb::bson::doc;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{handlers::route_handler::json_error, models::{docker_models, load_balancer_models::ActiveServiceDirectory}, utils::mongodb_utils::DBCollection};
+
+/// weight:[type usize] - share of requests the container receives under weighted behaviors
+#[derive(Deserialize)]
+pub struct UpdateContainerPayload {
+    weight: Option<usize>
+}
+
+///container_id is the docker_container_id of the container
+#[debug_handler]
+pub async fn update_container(Path(container_id): Path<String>, payload: Result<Json<UpdateContainerPayload>, JsonRejection>) -> impl IntoResponse{
+    let Json(payload) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return json_error(StatusCode::BAD_REQUEST, rejection.body_text()).into_response()
+    };
+    let weight = match payload.weight {
+        Some(weight) => weight,
+        None => return json_error(StatusCode::BAD_REQUEST, "[ERROR] nothing to update").into_response()
+    };
+    match DBCollection::CONTAINERS.collection::<docker_models::Container>().await.find_one_and_update(doc!{
+        "container_id": &container_id
+    }, doc!{
+        "$set": { "weight": weight as i64 }
+    }, None).await {
+        Ok(Some(_)) => {
+            ActiveServiceDirectory::set_container_weight(&container_id, weight).await;
+            (StatusCode::OK, Json(json!({ "container_id": container_id, "weight": weight }))).into_response()
+        },
+        Ok(None) => json_error(StatusCode::NOT_FOUND, format!("[ERROR] Cannot find container {}", container_id)).into_response(),
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, format!("[ERROR] Failed in updating container {}", container_id)).into_response()
+    }
+}
@@ -0,0 +1,16 @@
+use axum::{response::IntoResponse, Json};
+use axum_macros::debug_handler;
+use hyper::StatusCode;
+use serde_json::json;
+
+use crate::utils::upstream_utils::upstream_clients;
+
+///returns the pool settings of the upstream clients and the connection stats of every destination
+#[debug_handler]
+pub async fn get_upstream_metrics() -> impl IntoResponse{
+    let upstream_clients = upstream_clients();
+    (StatusCode::OK, Json(json!({
+        "pool": upstream_clients.config,
+        "upstreams": upstream_clients.stats().await
+    }))).into_response()
+}
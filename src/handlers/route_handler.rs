@@ -1,17 +1,37 @@
+use std::{collections::HashMap, str::FromStr};
 
-
-use std::str::FromStr;
-
-use axum::{extract::Path, response::IntoResponse, Json};
+use axum::{extract::{rejection::JsonRejection, Path}, response::{IntoResponse, Response}, Json};
 use axum_macros::debug_handler;
-use hyper::StatusCode;
-use mongodb::bson::{doc, oid::ObjectId};
-use serde::Deserialize;
+use hyper::{header::{HeaderName, HeaderValue}, Method, StatusCode};
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 
-use crate::{models::{docker_models::{self, RouteInsert, RouteTypes}, load_balancer_models::ActiveServiceDirectory}, utils::{docker_utils, mongodb_utils::DBCollection}};
+use crate::{models::{docker_models::{self, AffinityConfig, AffinitySource, BackendConfig, BodyConfig, CircuitBreakerConfig, ContainerTemplate, HealthCheckConfig, IdleConfig, PathRewriteConfig, PathRewriteMode, ProxyHeadersConfig, ReadinessConfig, RestartPolicyName, RetryConfig, RouteInsert, RoutePredicates, RouteTypes, RouteVariant, ScalingConfig, TimeoutConfig, TrafficSplitConfig, ValuePredicate, PRIMARY_VARIANT}, load_balancer_models::ActiveServiceDirectory}, utils::{docker_utils, load_balancer_utils::LoadBalancerBehavior, mongodb_utils::DBCollection, route_table_utils::{self, AddressSegment}, upstream_utils}};
 /// addres:[type String] - the general route the router will try to match it with \n
-/// exposed_port:[type String] - the container port it will try listening to. Match it with the docker-file exposed port config 
+///     segments can be :name parameters and a last *name catch-all, addresses starting with ~ are regexes anchored to the start of the path.
+///     parameters are sent as x-route-param-<name> headers and can be used as {name} in the prefix and rewrite replacement
+/// exposed_port:[type String] - the container port it will try listening to. Match it with the docker-file exposed port config
 /// docker_image_id:[type String] - the ID of the created docker-image-file
+/// upstream:[type String] - host:port or url a static route forwards to
+/// behavior:[type String] - load balancer behavior of a container route, defaults to round_robin
+/// affinity:[type AffinityConfig] - pins requests of a container route to a container, implies the consistent_hash behavior
+/// scaling:[type ScalingConfig] - lets the autoscaler manage the container count of a container route
+/// idle:[type IdleConfig] - stops or removes containers of a container route once idle for timeout seconds, timeout must be greater than 0
+/// readiness:[type ReadinessConfig] - probe and start timeout requests wait on after a container starts, the probe path starts with / and start_timeout is greater than 0
+/// health_check:[type HealthCheckConfig] - probes and ejects unhealthy containers of a container route
+/// container:[type ContainerTemplate] - env, mounts, limits and command of the containers of a container route
+/// body:[type BodyConfig] - maximum request body size and the size up to which request bodies are buffered for retries
+/// timeout:[type TimeoutConfig] - total request timeout and the idle timeout between chunks of a streamed response
+/// backend:[type BackendConfig] - scheme and tls verification used to connect to the containers or upstream of the route
+/// retry:[type RetryConfig] - attempts, backoff and failures on which a request is retried
+/// circuit_breaker:[type CircuitBreakerConfig] - failure rate at which the containers of a container route stop receiving requests
+/// proxy_headers:[type ProxyHeadersConfig] - host header sent to the containers or upstream of the route
+/// rewrite:[type PathRewriteConfig] - strips the address from the path, replaces it with the prefix or rewrites it with a regex
+/// host:[type String] - hostname or wildcard such as *.example.com the route is limited to, routes without one match any host
+/// predicates:[type RoutePredicates] - methods, headers, query parameters and cookies a request must match, requests that only miss the method get a 405
+/// traffic_split:[type TrafficSplitPayload] - variants of a container route with their own image and weight percent, the route image serves the rest
 
 #[derive(Deserialize)]
 pub struct AddRoutePayload {
@@ -19,92 +39,970 @@ pub struct AddRoutePayload {
     exposed_port: String,
     docker_image_id: Option<String>,
     route_type: String,
-    prefix: Option<String>
+    prefix: Option<String>,
+    upstream: Option<String>,
+    behavior: Option<String>,
+    #[serde(flatten)]
+    configs: RouteConfigPayload,
+    host: Option<String>,
+    traffic_split: Option<TrafficSplitPayload>
 }
-#[debug_handler]
-pub async fn add_route(Json(payload): Json<AddRoutePayload>) -> impl IntoResponse{
-    
-    if payload.route_type == RouteTypes::CONTAINER.to_string() {
-        let register_result = docker_utils::register_docker_image(&payload.docker_image_id.unwrap()).await;
-        match register_result {
-            Ok(registered_image)=>{
-                let route_doc = RouteInsert { 
-                    mongo_image: Some(registered_image), 
-                    address: payload.address.clone(), 
-                    exposed_port: payload.exposed_port,
-                    route_type: payload.route_type,
-                    prefix:payload.prefix,
-                };
-                match DBCollection::ROUTES.collection::<RouteInsert>().await.insert_one(route_doc, None).await {
-                    Ok(route_insert) =>{
-                        return (StatusCode::OK, format!("[SUCCESS] Created route (ref: {})", route_insert.inserted_id)).into_response();
-                    }
-                    Err(_)=>{
-                        return (StatusCode::INTERNAL_SERVER_ERROR, format!("[ERROR] Failed in creating route {}", payload.address)).into_response();
-                    }
-                }
-            }
-            Err(err)=>{
-                return err.into_response()
-            }
+
+///the configs of a route that are validated and stored the same way when it is added or updated
+#[derive(Deserialize, Serialize)]
+pub struct RouteConfigPayload {
+    affinity: Option<AffinityConfig>,
+    scaling: Option<ScalingConfig>,
+    idle: Option<IdleConfig>,
+    readiness: Option<ReadinessConfig>,
+    health_check: Option<HealthCheckConfig>,
+    container: Option<ContainerTemplate>,
+    body: Option<BodyConfig>,
+    timeout: Option<TimeoutConfig>,
+    backend: Option<BackendConfig>,
+    retry: Option<RetryConfig>,
+    circuit_breaker: Option<CircuitBreakerConfig>,
+    proxy_headers: Option<ProxyHeadersConfig>,
+    rewrite: Option<PathRewriteConfig>,
+    predicates: Option<RoutePredicates>
+}
+
+///every field is optional, only the provided fields are updated
+#[derive(Deserialize)]
+pub struct UpdateRoutePayload {
+    address: Option<String>,
+    exposed_port: Option<String>,
+    docker_image_id: Option<String>,
+    route_type: Option<String>,
+    prefix: Option<String>,
+    upstream: Option<String>,
+    behavior: Option<String>,
+    #[serde(flatten)]
+    configs: RouteConfigPayload,
+    host: Option<String>,
+    traffic_split: Option<TrafficSplitPayload>
+}
+
+///json representation of a stored route
+#[derive(Serialize)]
+pub struct RouteResponse {
+    _id: String,
+    mongo_image: Option<String>,
+    address: String,
+    exposed_port: String,
+    route_type: String,
+    prefix: Option<String>,
+    upstream: Option<String>,
+    affinity: Option<AffinityConfig>,
+    scaling: Option<ScalingConfig>,
+    idle: Option<IdleConfig>,
+    readiness: Option<ReadinessConfig>,
+    health_check: Option<HealthCheckConfig>,
+    container: Option<ContainerTemplate>,
+    body: Option<BodyConfig>,
+    timeout: Option<TimeoutConfig>,
+    backend: Option<BackendConfig>,
+    retry: Option<RetryConfig>,
+    circuit_breaker: Option<CircuitBreakerConfig>,
+    proxy_headers: Option<ProxyHeadersConfig>,
+    rewrite: Option<PathRewriteConfig>,
+    host: Option<String>,
+    predicates: Option<RoutePredicates>,
+    traffic_split: Option<TrafficSplitPayload>
+}
+
+impl From<docker_models::Route> for RouteResponse {
+    fn from(route: docker_models::Route) -> Self {
+        RouteResponse {
+            _id: route._id.to_hex(),
+            mongo_image: route.mongo_image.map(|mongo_image| mongo_image.to_hex()),
+            address: route.address,
+            exposed_port: route.exposed_port,
+            route_type: route.route_type,
+            prefix: route.prefix,
+            upstream: route.upstream,
+            affinity: route.affinity,
+            scaling: route.scaling,
+            idle: route.idle,
+            readiness: route.readiness,
+            health_check: route.health_check,
+            container: route.container,
+            body: route.body,
+            timeout: route.timeout,
+            backend: route.backend,
+            retry: route.retry,
+            circuit_breaker: route.circuit_breaker,
+            proxy_headers: route.proxy_headers,
+            rewrite: route.rewrite,
+            host: route.host,
+            predicates: route.predicates,
+            traffic_split: route.traffic_split.map(TrafficSplitPayload::from)
+        }
+    }
+}
+
+///variant of a traffic split as sent to and returned by the api
+#[derive(Deserialize, Serialize)]
+pub struct RouteVariantPayload {
+    name: String,
+    docker_image_id: String,
+    weight: u32
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct TrafficSplitPayload {
+    variants: Vec<RouteVariantPayload>,
+    pin_header: Option<String>,
+    pin_cookie: Option<String>,
+    #[serde(default, skip_deserializing)]
+    primary_weight: u32
+}
+
+impl From<TrafficSplitConfig> for TrafficSplitPayload {
+    fn from(traffic_split: TrafficSplitConfig) -> Self {
+        TrafficSplitPayload {
+            primary_weight: traffic_split.primary_weight(),
+            variants: traffic_split.variants.into_iter().map(|variant| RouteVariantPayload {
+                name: variant.name,
+                docker_image_id: variant.docker_image_id,
+                weight: variant.weight
+            }).collect(),
+            pin_header: traffic_split.pin_header,
+            pin_cookie: traffic_split.pin_cookie
+        }
+    }
+}
+
+///sets the weights of the variants of a traffic split, variants that are not named keep their weight
+#[derive(Deserialize)]
+pub struct UpdateRouteWeightsPayload {
+    weights: HashMap<String, u32>
+}
+
+///an error that responds with a json body of {"error": message}
+pub struct JsonError {
+    status: StatusCode,
+    message: String
+}
+
+impl IntoResponse for JsonError {
+    fn into_response(self) -> Response {
+        (self.status, Json(json!({ "error": self.message }))).into_response()
+    }
+}
+
+pub fn json_error(status:StatusCode, message:impl Into<String>) -> JsonError {
+    JsonError { status, message: message.into() }
+}
+
+fn parse_route_id(route_id:&str) -> Result<ObjectId, JsonError> {
+    ObjectId::from_str(route_id).map_err(|_| json_error(StatusCode::BAD_REQUEST, format!("[ERROR] {} is not a valid route id", route_id)))
+}
+
+///addresses are paths whose segments can be :name parameters and a last *name catch-all, or regexes starting with ~
+fn validate_address(address:&str) -> Result<(), JsonError> {
+    if let Some(address_pattern) = route_table_utils::address_pattern(address) {
+        return match address_pattern {
+            Ok(_) => Ok(()),
+            Err(error) => Err(json_error(StatusCode::BAD_REQUEST, format!("[ERROR] address pattern is invalid: {}", error)))
         };
     }
-    else if payload.route_type == RouteTypes::STATIC.to_string(){ 
-        let route_doc = RouteInsert { 
-            mongo_image: None, 
-            address: payload.address.clone(), 
-            exposed_port: payload.exposed_port,
-            route_type: payload.route_type,
-            prefix: payload.prefix
+    if !address.starts_with('/') {
+        return Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] address must start with '/' or '~'"));
+    }
+    let address_segments: Vec<AddressSegment> = route_table_utils::address_segments(address).collect();
+    let mut param_names: Vec<&str> = Vec::new();
+    for (index, address_segment) in address_segments.iter().enumerate() {
+        let param_name = match address_segment {
+            AddressSegment::CatchAll(_) if index + 1 < address_segments.len() => return Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] address catch-all must be the last segment")),
+            AddressSegment::Param(param_name) | AddressSegment::CatchAll(param_name) => *param_name,
+            AddressSegment::Literal(_) => continue
         };
-        match DBCollection::ROUTES.collection::<RouteInsert>().await.insert_one(route_doc, None).await {
-            Ok(route_insert) =>{
-                return (StatusCode::OK, format!("[SUCCESS] Created route (ref: {})", route_insert.inserted_id)).into_response()
-            }
-            Err(_)=>{
-                return (StatusCode::INTERNAL_SERVER_ERROR, format!("[ERROR] Failed in creating route {}", payload.address)).into_response()
-            }
+        if param_name.is_empty() || !param_name.chars().all(|character| character.is_ascii_alphanumeric() || character == '_' || character == '-') {
+            return Err(json_error(StatusCode::BAD_REQUEST, format!("[ERROR] address parameter {} must be named with letters, digits, '_' or '-'", param_name)));
+        }
+        if param_names.contains(&param_name) {
+            return Err(json_error(StatusCode::BAD_REQUEST, format!("[ERROR] address parameter {} is used more than once", param_name)));
         }
+        param_names.push(param_name);
+    }
+    Ok(())
+}
+
+///a route matching the same requests as an existing route would shadow it or never be reached
+async fn validate_route_conflict(host:Option<&str>, address:&str, predicates:Option<&RoutePredicates>, excluded_route:Option<ObjectId>) -> Result<(), JsonError> {
+    match route_table_utils::route_table().await.find_conflict(host, address, predicates, excluded_route) {
+        Some(conflicting_entry) => Err(json_error(StatusCode::CONFLICT, format!("[ERROR] Route {} already matches the requests of {}", conflicting_entry.route._id.to_hex(), address))),
+        None => Ok(())
+    }
+}
+
+fn validate_exposed_port(exposed_port:&str) -> Result<(), JsonError> {
+    match exposed_port.parse::<u16>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(json_error(StatusCode::BAD_REQUEST, format!("[ERROR] exposed_port {} is not a valid port", exposed_port)))
+    }
+}
+
+fn validate_route_type(route_type:&str) -> Result<RouteTypes, JsonError> {
+    RouteTypes::from_route_type(route_type).ok_or_else(|| json_error(StatusCode::BAD_REQUEST, format!("[ERROR] route_type {} is not supported", route_type)))
+}
+
+fn validate_upstream(upstream:&str) -> Result<(), JsonError> {
+    let upstream_url = if upstream.contains("://") { upstream.to_string() } else { format!("https://{}", upstream) };
+    match reqwest::Url::parse(&upstream_url) {
+        Ok(url) if url.host_str().is_some() => Ok(()),
+        _ => Err(json_error(StatusCode::BAD_REQUEST, format!("[ERROR] upstream {} is not a valid host:port or url", upstream)))
+    }
+}
+
+fn validate_behavior(behavior:&str) -> Result<LoadBalancerBehavior, JsonError> {
+    LoadBalancerBehavior::from_behavior(behavior).ok_or_else(|| json_error(StatusCode::BAD_REQUEST, format!("[ERROR] behavior {} is not supported", behavior)))
+}
+
+fn validate_scaling(scaling:&Option<ScalingConfig>) -> Result<(), JsonError> {
+    let scaling = match scaling {
+        Some(scaling) => scaling,
+        None => return Ok(())
+    };
+    if scaling.max_replicas == 0 || scaling.min_replicas > scaling.max_replicas {
+        return Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] scaling requires 0 < max_replicas and min_replicas <= max_replicas"));
+    }
+    if scaling.scale_down_in_flight >= scaling.scale_up_in_flight {
+        return Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] scaling scale_down_in_flight must be lower than scale_up_in_flight"));
+    }
+    Ok(())
+}
+
+fn validate_idle(idle:&Option<IdleConfig>) -> Result<(), JsonError> {
+    match idle {
+        Some(idle) if idle.timeout == 0 => Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] idle timeout must be greater than 0")),
+        _ => Ok(())
+    }
+}
+
+///probe paths are appended to the upstream of the container
+fn validate_readiness(readiness:&Option<ReadinessConfig>) -> Result<(), JsonError> {
+    let readiness = match readiness {
+        Some(readiness) => readiness,
+        None => return Ok(())
+    };
+    if readiness.start_timeout == Some(0) {
+        return Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] readiness start_timeout must be greater than 0"));
+    }
+    if readiness.path.as_deref().is_some_and(|path| !path.starts_with('/')) {
+        return Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] readiness path must start with /"));
+    }
+    Ok(())
+}
+
+fn validate_health_check(health_check:&Option<HealthCheckConfig>) -> Result<(), JsonError> {
+    let health_check = match health_check {
+        Some(health_check) => health_check,
+        None => return Ok(())
+    };
+    if health_check.healthy_threshold == 0 || health_check.unhealthy_threshold == 0 || health_check.passive_failure_threshold == 0 {
+        return Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] health_check thresholds must be greater than 0"));
+    }
+    if health_check.interval > 0 && health_check.timeout == 0 {
+        return Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] health_check timeout must be greater than 0"));
+    }
+    Ok(())
+}
+
+fn validate_container_template(container:&Option<ContainerTemplate>) -> Result<(), JsonError> {
+    let container = match container {
+        Some(container) => container,
+        None => return Ok(())
+    };
+    if container.env.as_ref().is_some_and(|env| env.keys().any(|key| key.is_empty() || key.contains('='))) {
+        return Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] container env names must be non-empty and cannot contain '='"));
+    }
+    if container.binds.as_ref().is_some_and(|binds| binds.iter().any(|bind| bind.split(':').count() < 2)) {
+        return Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] container binds must be host_path:container_path[:options]"));
+    }
+    if container.volumes.as_ref().is_some_and(|volumes| volumes.iter().any(|volume| !volume.starts_with('/'))) {
+        return Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] container volumes must be absolute container paths"));
+    }
+    if container.memory.is_some_and(|memory| memory <= 0) || container.cpus.is_some_and(|cpus| cpus <= 0.0) {
+        return Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] container memory and cpus must be greater than 0"));
+    }
+    if let Some(restart_policy) = &container.restart_policy {
+        if restart_policy.max_retries.is_some() && restart_policy.name != RestartPolicyName::OnFailure {
+            return Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] container restart_policy max_retries requires the on_failure policy"));
+        }
+    }
+    Ok(())
+}
+
+fn validate_body(body:&Option<BodyConfig>) -> Result<(), JsonError> {
+    match body {
+        Some(BodyConfig { max_size: Some(0), .. }) => Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] body max_size must be greater than 0")),
+        _ => Ok(())
+    }
+}
+
+fn validate_timeout(timeout:&Option<TimeoutConfig>) -> Result<(), JsonError> {
+    match timeout {
+        Some(TimeoutConfig { request_timeout: Some(0), .. }) | Some(TimeoutConfig { idle_timeout: Some(0), .. }) => Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] timeout request_timeout and idle_timeout must be greater than 0")),
+        _ => Ok(())
+    }
+}
+
+fn validate_backend(backend:&Option<BackendConfig>) -> Result<(), JsonError> {
+    match backend {
+        Some(backend) => upstream_utils::validate_backend(backend).map_err(|err_string| json_error(StatusCode::BAD_REQUEST, format!("[ERROR] backend {}", err_string))),
+        None => Ok(())
+    }
+}
+
+fn validate_retry(retry:&Option<RetryConfig>) -> Result<(), JsonError> {
+    match retry {
+        Some(RetryConfig { max_attempts: 0, .. }) => Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] retry max_attempts must be greater than 0")),
+        Some(retry) if retry.base_delay > retry.max_delay => Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] retry base_delay must not be greater than max_delay")),
+        _ => Ok(())
+    }
+}
+
+fn validate_circuit_breaker(circuit_breaker:&Option<CircuitBreakerConfig>) -> Result<(), JsonError> {
+    match circuit_breaker {
+        Some(circuit_breaker) if circuit_breaker.window == 0 || circuit_breaker.half_open_probes == 0 => Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] circuit_breaker window and half_open_probes must be greater than 0")),
+        Some(circuit_breaker) if circuit_breaker.minimum_requests > circuit_breaker.window => Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] circuit_breaker minimum_requests must not be greater than window")),
+        Some(circuit_breaker) if !(circuit_breaker.failure_rate > 0.0 && circuit_breaker.failure_rate <= 1.0) => Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] circuit_breaker failure_rate must be greater than 0 and at most 1")),
+        _ => Ok(())
+    }
+}
+
+fn validate_proxy_headers(proxy_headers:&Option<ProxyHeadersConfig>) -> Result<(), JsonError> {
+    match proxy_headers.as_ref().and_then(|proxy_headers| proxy_headers.host.as_deref()) {
+        Some(host) if host.is_empty() || HeaderValue::from_str(host).is_err() => Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] proxy_headers host is not a valid header value")),
+        _ => Ok(())
+    }
+}
+
+///replace rewrites need the prefix of the route and regex rewrites a pattern that compiles
+fn validate_rewrite(rewrite:&Option<PathRewriteConfig>, prefix:Option<&str>) -> Result<(), JsonError> {
+    let rewrite = match rewrite {
+        Some(rewrite) => rewrite,
+        None => return Ok(())
+    };
+    match (rewrite.mode, rewrite.pattern.as_deref()) {
+        (PathRewriteMode::Replace, _) if prefix.is_none() => Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] rewrite mode replace requires the route to have a prefix")),
+        (PathRewriteMode::Regex, None) => Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] rewrite mode regex requires a pattern")),
+        (PathRewriteMode::Regex, Some(pattern)) => match Regex::new(pattern) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(json_error(StatusCode::BAD_REQUEST, format!("[ERROR] rewrite pattern is invalid: {}", error)))
+        },
+        _ => Ok(())
+    }
+}
+
+///hosts are a hostname or a wildcard of a single label such as *.example.com, without a port
+fn validate_host(host:&Option<String>) -> Result<(), JsonError> {
+    let host = match host {
+        Some(host) => host,
+        None => return Ok(())
+    };
+    let hostname = host.strip_prefix("*.").unwrap_or(host);
+    let is_hostname = hostname.split('.').all(|label| {
+        !label.is_empty() && label.len() <= 63 && label.chars().all(|character| character.is_ascii_alphanumeric() || character == '-')
+    });
+    if is_hostname {
+        Ok(())
     }else{
-        return (StatusCode::BAD_REQUEST, format!("[ERROR] Bad Request")).into_response();
+        Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] host must be a hostname or a wildcard such as *.example.com"))
     }
-    
 }
 
+fn validate_value_predicates(kind:&str, predicates:&[ValuePredicate]) -> Result<(), JsonError> {
+    for predicate in predicates {
+        if predicate.name.is_empty() {
+            return Err(json_error(StatusCode::BAD_REQUEST, format!("[ERROR] {} predicates require a name", kind)));
+        }
+        match (&predicate.value, &predicate.pattern) {
+            (Some(_), Some(_)) => return Err(json_error(StatusCode::BAD_REQUEST, format!("[ERROR] {} predicate {} can only have a value or a pattern", kind, predicate.name))),
+            (None, Some(pattern)) => if let Err(error) = Regex::new(pattern) {
+                return Err(json_error(StatusCode::BAD_REQUEST, format!("[ERROR] {} predicate {} has an invalid pattern: {}", kind, predicate.name, error)));
+            },
+            _ => ()
+        }
+    }
+    Ok(())
+}
 
-#[debug_handler]
-pub async fn remove_route(Path(route_id): Path<String>) -> impl IntoResponse{
+///methods and header names have to be valid http tokens and patterns have to compile
+fn validate_predicates(predicates:&Option<RoutePredicates>) -> Result<(), JsonError> {
+    let predicates = match predicates {
+        Some(predicates) => predicates,
+        None => return Ok(())
+    };
+    if let Some(method) = predicates.methods.iter().find(|method| Method::from_bytes(method.to_ascii_uppercase().as_bytes()).is_err()) {
+        return Err(json_error(StatusCode::BAD_REQUEST, format!("[ERROR] {} is not a valid method", method)));
+    }
+    if let Some(header) = predicates.headers.iter().find(|header| HeaderName::from_bytes(header.name.as_bytes()).is_err()) {
+        return Err(json_error(StatusCode::BAD_REQUEST, format!("[ERROR] {} is not a valid header name", header.name)));
+    }
+    validate_value_predicates("header", &predicates.headers)
+        .and(validate_value_predicates("query", &predicates.query))
+        .and(validate_value_predicates("cookie", &predicates.cookies))
+}
 
-    let o_id: ObjectId = ObjectId::from_str(route_id.as_str()).unwrap();
-    let route_result = DBCollection::ROUTES.collection::<docker_models::Route>().await.find_one(doc!{
+///variant weights are percentages the route image receives the rest of
+fn validate_variant_weights<'a>(weights:impl Iterator<Item = &'a u32>) -> Result<(), JsonError> {
+    if weights.sum::<u32>() > 100 {
+        return Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] the weights of the variants cannot add up to more than 100"));
+    }
+    Ok(())
+}
+
+///variant names are unique and pinnable so they cannot be primary, the pin header has to be a valid header name
+fn validate_traffic_split(traffic_split:&Option<TrafficSplitPayload>) -> Result<(), JsonError> {
+    let traffic_split = match traffic_split {
+        Some(traffic_split) => traffic_split,
+        None => return Ok(())
+    };
+    let mut variant_names: Vec<&str> = Vec::new();
+    for variant in traffic_split.variants.iter() {
+        if variant.name.is_empty() || variant.name == PRIMARY_VARIANT || !variant.name.chars().all(|character| character.is_ascii_alphanumeric() || character == '_' || character == '-') {
+            return Err(json_error(StatusCode::BAD_REQUEST, format!("[ERROR] variant {} must be named with letters, digits, '_' or '-' and cannot be {}", variant.name, PRIMARY_VARIANT)));
+        }
+        if variant_names.contains(&variant.name.as_str()) {
+            return Err(json_error(StatusCode::BAD_REQUEST, format!("[ERROR] variant {} is used more than once", variant.name)));
+        }
+        variant_names.push(&variant.name);
+    }
+    if let Some(Err(_)) = traffic_split.pin_header.as_ref().map(|pin_header| HeaderName::from_bytes(pin_header.as_bytes())) {
+        return Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] pin_header is not a valid header name"));
+    }
+    validate_variant_weights(traffic_split.variants.iter().map(|variant| &variant.weight))
+}
+
+///registers the images of the variants, each variant needs an image other than the image of the route and the other variants
+async fn register_traffic_split(traffic_split:TrafficSplitPayload, mongo_image:Option<ObjectId>) -> Result<TrafficSplitConfig, Response> {
+    let mongo_image = match mongo_image {
+        Some(mongo_image) => mongo_image,
+        None => return Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] traffic_split requires a container route").into_response())
+    };
+    let mut variant_images = vec![mongo_image];
+    let mut variants: Vec<RouteVariant> = Vec::new();
+    for variant in traffic_split.variants {
+        let variant_image = docker_utils::register_docker_image(&variant.docker_image_id).await.map_err(|err| err.into_response())?;
+        if variant_images.contains(&variant_image) {
+            return Err(json_error(StatusCode::BAD_REQUEST, format!("[ERROR] variant {} needs an image of its own", variant.name)).into_response());
+        }
+        variant_images.push(variant_image);
+        variants.push(RouteVariant {
+            name: variant.name,
+            docker_image_id: variant.docker_image_id,
+            mongo_image: variant_image,
+            weight: variant.weight
+        });
+    }
+    Ok(TrafficSplitConfig {
+        variants,
+        pin_header: traffic_split.pin_header,
+        pin_cookie: traffic_split.pin_cookie
+    })
+}
+
+///returns the behavior to apply, affinity requires the consistent_hash behavior and defaults to it
+fn validate_affinity(affinity:&Option<AffinityConfig>, behavior:Option<LoadBalancerBehavior>) -> Result<Option<LoadBalancerBehavior>, JsonError> {
+    let affinity = match affinity {
+        Some(affinity) => affinity,
+        None => return Ok(behavior)
+    };
+    if affinity.source == AffinitySource::Header && affinity.header_name.is_none() {
+        return Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] affinity header_name is required for header sources"));
+    }
+    match behavior {
+        None | Some(LoadBalancerBehavior::ConsistentHash) => Ok(Some(LoadBalancerBehavior::ConsistentHash)),
+        Some(_) => Err(json_error(StatusCode::BAD_REQUEST, "[ERROR] affinity requires the consistent_hash behavior"))
+    }
+}
+
+///persists the behavior on the load balancer of the image and applies it to the running load balancer of the route
+async fn apply_behavior(mongo_image:&ObjectId, load_balancer_key:&String, behavior:LoadBalancerBehavior) -> Result<(), JsonError> {
+    match docker_utils::set_load_balancer_behavior(mongo_image, behavior).await {
+        Ok(_) => {
+            ActiveServiceDirectory::set_load_balancer_behavior(load_balancer_key, behavior).await;
+            Ok(())
+        },
+        Err(_) => Err(json_error(StatusCode::INTERNAL_SERVER_ERROR, format!("[ERROR] Failed in setting the behavior of {}", load_balancer_key)))
+    }
+}
+
+///applies the behavior to the load balancers of the image of the route and of its variants
+async fn apply_route_behavior(route:&docker_models::Route, behavior:LoadBalancerBehavior) -> Result<(), JsonError> {
+    for (variant, mongo_image) in route.variant_images() {
+        apply_behavior(&mongo_image, &route.variant_load_balancer_key(&variant), behavior).await?;
+    }
+    Ok(())
+}
+
+async fn find_route(o_id:&ObjectId) -> Result<docker_models::Route, JsonError> {
+    match DBCollection::ROUTES.collection::<docker_models::Route>().await.find_one(doc!{
         "_id": o_id
-    }, None).await.unwrap();
-
-    match route_result {
-        Some(route)=>{
-            
-            match DBCollection::ROUTES.collection::<docker_models::Route>().await.delete_one(doc!{
-                "_id": &route._id
-            }, None).await {
-                Ok(_res)=>{
-                    println!("[PROCESS] Successfully deleted route {} from db", &o_id);
-                    if ActiveServiceDirectory::remove_load_balancer(&route.address).await.is_some(){
-                        println!("[PROCESS] Successfully removed {} from the router",route.address);
+    }, None).await {
+        Ok(Some(route)) => Ok(route),
+        Ok(None) => Err(json_error(StatusCode::NOT_FOUND, format!("[ERROR] Cannot find route {}", o_id))),
+        Err(_) => Err(json_error(StatusCode::INTERNAL_SERVER_ERROR, format!("[ERROR] Failed in fetching route {}", o_id)))
+    }
+}
+
+///validates the provided configs of a route, the rewrite is checked against the prefix the route ends up with
+fn validate_route_configs(configs:&RouteConfigPayload, prefix:Option<&str>) -> Result<(), JsonError> {
+    validate_scaling(&configs.scaling)?;
+    validate_idle(&configs.idle)?;
+    validate_readiness(&configs.readiness)?;
+    validate_health_check(&configs.health_check)?;
+    validate_container_template(&configs.container)?;
+    validate_body(&configs.body)?;
+    validate_timeout(&configs.timeout)?;
+    validate_backend(&configs.backend)?;
+    validate_retry(&configs.retry)?;
+    validate_circuit_breaker(&configs.circuit_breaker)?;
+    validate_proxy_headers(&configs.proxy_headers)?;
+    validate_rewrite(&configs.rewrite, prefix)?;
+    validate_predicates(&configs.predicates)?;
+    Ok(())
+}
+
+///validates the configs of a new route before anything is registered
+fn validate_add_route_payload(payload:&AddRoutePayload) -> Result<(), JsonError> {
+    validate_address(&payload.address)?;
+    validate_exposed_port(&payload.exposed_port)?;
+    validate_route_configs(&payload.configs, payload.prefix.as_deref())?;
+    validate_host(&payload.host)?;
+    validate_traffic_split(&payload.traffic_split)?;
+    Ok(())
+}
+
+#[debug_handler]
+pub async fn add_route(payload: Result<Json<AddRoutePayload>, JsonRejection>) -> impl IntoResponse{
+    let Json(payload) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return json_error(StatusCode::BAD_REQUEST, rejection.body_text()).into_response()
+    };
+    if let Err(err_response) = validate_add_route_payload(&payload) {
+        return err_response.into_response();
+    }
+    let behavior = match payload.behavior.as_deref().map(validate_behavior).transpose().and_then(|behavior| validate_affinity(&payload.configs.affinity, behavior)) {
+        Ok(behavior) => behavior,
+        Err(err_response) => return err_response.into_response()
+    };
+    //checked before the images are registered so a conflicting route leaves nothing behind
+    let route_host = payload.host.as_ref().map(|host| host.to_ascii_lowercase());
+    if let Err(err_response) = validate_route_conflict(route_host.as_deref(), &payload.address, payload.configs.predicates.as_ref(), None).await {
+        return err_response.into_response();
+    }
+    let mongo_image = match validate_route_type(&payload.route_type) {
+        Ok(RouteTypes::CONTAINER) => {
+            let docker_image_id = match &payload.docker_image_id {
+                Some(docker_image_id) => docker_image_id,
+                None => return json_error(StatusCode::BAD_REQUEST, "[ERROR] docker_image_id is required for container routes").into_response()
+            };
+            match docker_utils::register_docker_image(docker_image_id).await {
+                Ok(registered_image) => Some(registered_image),
+                Err(err) => return err.into_response()
+            }
+        },
+        Ok(RouteTypes::STATIC) => {
+            if let Some(Err(err_response)) = payload.upstream.as_deref().map(validate_upstream) {
+                return err_response.into_response();
+            }
+            None
+        },
+        Err(err_response) => return err_response.into_response()
+    };
+    let traffic_split = match payload.traffic_split {
+        Some(traffic_split) => match register_traffic_split(traffic_split, mongo_image).await {
+            Ok(traffic_split) => Some(traffic_split),
+            Err(err_response) => return err_response
+        },
+        None => None
+    };
+    let RouteConfigPayload { affinity, scaling, idle, readiness, health_check, container, body, timeout, backend, retry, circuit_breaker, proxy_headers, rewrite, predicates } = payload.configs;
+    let route_doc = RouteInsert {
+        mongo_image,
+        address: payload.address.clone(),
+        exposed_port: payload.exposed_port,
+        route_type: payload.route_type,
+        prefix: payload.prefix,
+        upstream: payload.upstream,
+        affinity,
+        scaling,
+        idle,
+        readiness,
+        health_check,
+        container,
+        body,
+        timeout,
+        backend,
+        retry,
+        circuit_breaker,
+        proxy_headers,
+        rewrite,
+        host: route_host,
+        predicates,
+        traffic_split,
+    };
+    match DBCollection::ROUTES.collection::<RouteInsert>().await.insert_one(route_doc, None).await {
+        Ok(route_insert) =>{
+            let route_o_id = route_insert.inserted_id.as_object_id();
+            if let (Some(route_o_id), Some(behavior)) = (route_o_id, behavior) {
+                let applied_behavior = match find_route(&route_o_id).await {
+                    Ok(route) => apply_route_behavior(&route, behavior).await,
+                    Err(err_response) => Err(err_response)
+                };
+                if let Err(err_response) = applied_behavior {
+                    //the route is not left stored without its behavior, it was never added to the route table
+                    if let Err(err) = DBCollection::ROUTES.collection::<RouteInsert>().await.delete_one(doc!{
+                        "_id": route_o_id
+                    }, None).await {
+                        println!("[ERROR] Failed in removing route {} whose behavior was not applied: {}", route_o_id, err);
                     }
+                    return err_response.into_response();
                 }
-                Err(e)=>{
-                    println!("[PROCESS] Cannot find {} to delete", &o_id);
+            }
+            route_table_utils::refresh_route_table().await;
+            let route_id = route_o_id.map(|o_id| o_id.to_hex()).unwrap_or_default();
+            (StatusCode::CREATED, Json(json!({ "_id": route_id }))).into_response()
+        }
+        Err(_)=>{
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, format!("[ERROR] Failed in creating route {}", payload.address)).into_response()
+        }
+    }
+}
+
+#[debug_handler]
+pub async fn list_routes() -> impl IntoResponse{
+    let mut cursor = match DBCollection::ROUTES.collection::<docker_models::Route>().await.find(doc!{}, None).await {
+        Ok(cursor) => cursor,
+        Err(_) => return json_error(StatusCode::INTERNAL_SERVER_ERROR, "[ERROR] Failed in fetching routes").into_response()
+    };
+    let mut routes: Vec<RouteResponse> = Vec::new();
+    while let Ok(true) = cursor.advance().await {
+        if let Ok(route) = cursor.deserialize_current() {
+            routes.push(RouteResponse::from(route));
+        }
+    }
+    (StatusCode::OK, Json(routes)).into_response()
+}
+
+#[debug_handler]
+pub async fn get_route(Path(route_id): Path<String>) -> impl IntoResponse{
+    let o_id = match parse_route_id(&route_id) {
+        Ok(o_id) => o_id,
+        Err(err_response) => return err_response.into_response()
+    };
+    match find_route(&o_id).await {
+        Ok(route) => (StatusCode::OK, Json(RouteResponse::from(route))).into_response(),
+        Err(err_response) => err_response.into_response()
+    }
+}
+
+///returns the docker container ids of the load balancers of a container route and its variants
+async fn find_route_containers(o_id:&ObjectId, route:&docker_models::Route) -> Result<Vec<String>, JsonError>{
+    let mongo_images: Vec<ObjectId> = route.variant_images().into_iter().map(|(_, mongo_image)| mongo_image).collect();
+    if mongo_images.is_empty() {
+        return Err(json_error(StatusCode::BAD_REQUEST, format!("[ERROR] Route {} has no containers", o_id)));
+    }
+    let mut cursor = match DBCollection::LOADBALANCERS.collection::<docker_models::LoadBalancer>().await.find(doc!{
+        "mongo_image_reference": { "$in": mongo_images }
+    }, None).await {
+        Ok(cursor) => cursor,
+        Err(_) => return Err(json_error(StatusCode::INTERNAL_SERVER_ERROR, format!("[ERROR] Failed in fetching the containers of route {}", o_id)))
+    };
+    let mut containers: Vec<String> = Vec::new();
+    while let Ok(true) = cursor.advance().await {
+        if let Ok(mongo_load_balancer) = cursor.deserialize_current() {
+            containers.extend(mongo_load_balancer.containers);
+        }
+    }
+    Ok(containers)
+}
+
+///returns the containers of a container route with their health and load
+#[debug_handler]
+pub async fn get_route_containers(Path(route_id): Path<String>) -> impl IntoResponse{
+    let o_id = match parse_route_id(&route_id) {
+        Ok(o_id) => o_id,
+        Err(err_response) => return err_response.into_response()
+    };
+    let containers = match find_route(&o_id).await {
+        Ok(route) => match find_route_containers(&o_id, &route).await {
+            Ok(containers) => containers,
+            Err(err_response) => return err_response.into_response()
+        },
+        Err(err_response) => return err_response.into_response()
+    };
+    (StatusCode::OK, Json(ActiveServiceDirectory::get_container_statuses(&containers).await)).into_response()
+}
+
+///returns the route level circuit of a container route and the circuit of each of its containers
+/// 
+/// circuits are null until the route has received a request since the orchestrator started
+#[debug_handler]
+pub async fn get_route_circuit(Path(route_id): Path<String>) -> impl IntoResponse{
+    let o_id = match parse_route_id(&route_id) {
+        Ok(o_id) => o_id,
+        Err(err_response) => return err_response.into_response()
+    };
+    let route = match find_route(&o_id).await {
+        Ok(route) => route,
+        Err(err_response) => return err_response.into_response()
+    };
+    let containers = match find_route_containers(&o_id, &route).await {
+        Ok(containers) => containers,
+        Err(err_response) => return err_response.into_response()
+    };
+    let container_circuits: Vec<serde_json::Value> = ActiveServiceDirectory::get_container_statuses(&containers).await.into_iter()
+        .map(|container_status| json!({"container_id": container_status.container_id, "circuit": container_status.circuit}))
+        .collect();
+    let mut variant_circuits = serde_json::Map::new();
+    for (variant, _) in route.variant_images().into_iter().filter(|(variant, _)| variant != PRIMARY_VARIANT) {
+        let variant_circuit = ActiveServiceDirectory::get_load_balancer_circuit(&route.variant_load_balancer_key(&variant)).await;
+        variant_circuits.insert(variant, json!(variant_circuit));
+    }
+    (StatusCode::OK, Json(json!({
+        "circuit": ActiveServiceDirectory::get_load_balancer_circuit(&route.load_balancer_key()).await,
+        "variants": variant_circuits,
+        "containers": container_circuits
+    }))).into_response()
+}
+
+///serializes the value into the $set document of an update, errors when the value cannot be stored
+///returns the provided configs as the fields an update sets, configs left out are kept
+fn route_configs_set_doc(configs:&RouteConfigPayload) -> Result<Document, JsonError> {
+    let configs_doc = mongodb::bson::to_document(configs).map_err(|_| json_error(StatusCode::BAD_REQUEST, "[ERROR] route configs are invalid"))?;
+    Ok(configs_doc.into_iter().filter(|(_, value)| value != &Bson::Null).collect())
+}
+
+fn set_bson<T:Serialize>(set_doc:&mut Document, key:&str, value:&T) -> Result<(), JsonError> {
+    let value_bson = mongodb::bson::to_bson(value).map_err(|_| json_error(StatusCode::BAD_REQUEST, format!("[ERROR] {} is invalid", key)))?;
+    set_doc.insert(key, value_bson);
+    Ok(())
+}
+
+#[debug_handler]
+pub async fn update_route(Path(route_id): Path<String>, payload: Result<Json<UpdateRoutePayload>, JsonRejection>) -> impl IntoResponse{
+    let o_id = match parse_route_id(&route_id) {
+        Ok(o_id) => o_id,
+        Err(err_response) => return err_response.into_response()
+    };
+    let Json(payload) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return json_error(StatusCode::BAD_REQUEST, rejection.body_text()).into_response()
+    };
+    let route = match find_route(&o_id).await {
+        Ok(route) => route,
+        Err(err_response) => return err_response.into_response()
+    };
+
+    let behavior = match payload.behavior.as_deref().map(validate_behavior).transpose().and_then(|behavior| validate_affinity(&payload.configs.affinity, behavior)) {
+        Ok(behavior) => behavior,
+        Err(err_response) => return err_response.into_response()
+    };
+    if let Err(err_response) = validate_route_configs(&payload.configs, payload.prefix.as_deref().or(route.prefix.as_deref())) {
+        return err_response.into_response();
+    }
+    let mut set_doc = match route_configs_set_doc(&payload.configs) {
+        Ok(set_doc) => set_doc,
+        Err(err_response) => return err_response.into_response()
+    };
+    if let Some(address) = &payload.address {
+        if let Err(err_response) = validate_address(address) {
+            return err_response.into_response();
+        }
+        set_doc.insert("address", address);
+    }
+    if let Some(exposed_port) = &payload.exposed_port {
+        if let Err(err_response) = validate_exposed_port(exposed_port) {
+            return err_response.into_response();
+        }
+        set_doc.insert("exposed_port", exposed_port);
+    }
+    if let Some(prefix) = &payload.prefix {
+        set_doc.insert("prefix", prefix);
+    }
+    if let Some(host) = &payload.host {
+        if let Err(err_response) = validate_host(&payload.host) {
+            return err_response.into_response();
+        }
+        set_doc.insert("host", host.to_ascii_lowercase());
+    }
+    if let Err(err_response) = validate_traffic_split(&payload.traffic_split) {
+        return err_response.into_response();
+    }
+    //checked before the images are registered so a conflicting update leaves nothing behind
+    if payload.address.is_some() || payload.host.is_some() || payload.configs.predicates.is_some() {
+        let updated_host = payload.host.as_ref().map(|host| host.to_ascii_lowercase()).or_else(|| route.host.clone());
+        let updated_address = payload.address.as_deref().unwrap_or(&route.address);
+        let updated_predicates = payload.configs.predicates.as_ref().or(route.predicates.as_ref());
+        if let Err(err_response) = validate_route_conflict(updated_host.as_deref(), updated_address, updated_predicates, Some(route._id)).await {
+            return err_response.into_response();
+        }
+    }
+    let route_type_string = payload.route_type.clone().unwrap_or_else(|| route.route_type.clone());
+    let mongo_image = match validate_route_type(&route_type_string) {
+        Ok(RouteTypes::CONTAINER) => {
+            match (&payload.docker_image_id, route.mongo_image) {
+                (Some(docker_image_id), _) => match docker_utils::register_docker_image(docker_image_id).await {
+                    Ok(registered_image) => Some(registered_image),
+                    Err(err) => return err.into_response()
+                },
+                (None, Some(mongo_image)) => Some(mongo_image),
+                (None, None) => return json_error(StatusCode::BAD_REQUEST, "[ERROR] docker_image_id is required for container routes").into_response()
+            }
+        },
+        Ok(RouteTypes::STATIC) => {
+            if let Some(upstream) = &payload.upstream {
+                if let Err(err_response) = validate_upstream(upstream) {
+                    return err_response.into_response();
                 }
-            };
-            return (StatusCode::OK, "").into_response()
+                set_doc.insert("upstream", upstream);
+            }
+            None
+        },
+        Err(err_response) => return err_response.into_response()
+    };
+    set_doc.insert("route_type", &route_type_string);
+    set_doc.insert("mongo_image", mongo_image);
+    let traffic_split = match payload.traffic_split {
+        Some(traffic_split) => match register_traffic_split(traffic_split, mongo_image).await {
+            Ok(traffic_split) => Some(traffic_split),
+            Err(err_response) => return err_response
+        },
+        //static routes cannot split their traffic
+        None if mongo_image.is_none() => {
+            set_doc.insert("traffic_split", Bson::Null);
+            None
+        },
+        None => None
+    };
+    if let Some(traffic_split) = &traffic_split {
+        if let Err(err_response) = set_bson(&mut set_doc, "traffic_split", traffic_split) {
+            return err_response.into_response();
+        }
+    }
+
+    if DBCollection::ROUTES.collection::<docker_models::Route>().await.find_one_and_update(doc!{
+        "_id": &o_id
+    }, doc!{
+        "$set": set_doc
+    }, None).await.is_err() {
+        return json_error(StatusCode::INTERNAL_SERVER_ERROR, format!("[ERROR] Failed in updating route {}", o_id)).into_response();
+    }
 
+    //the running containers are only torn down once the update is stored,
+    //containers are built around the image, exposed port and container template so they cannot be reused when any changes
+    let requires_teardown = mongo_image != route.mongo_image || payload.exposed_port.as_ref().is_some_and(|exposed_port| exposed_port != &route.exposed_port) || payload.configs.container.is_some();
+    if requires_teardown {
+        ActiveServiceDirectory::teardown_route_load_balancer(&route).await;
+    }else if payload.address.as_ref().is_some_and(|address| address != &route.address) || payload.host.as_ref().is_some_and(|host| Some(host.to_ascii_lowercase()) != route.host) {
+        //the load balancers are keyed by host and address so they are rebuilt under the new ones on the next request
+        let route_images: Vec<ObjectId> = route.variant_images().into_iter().map(|(_, mongo_image)| mongo_image).collect();
+        ActiveServiceDirectory::teardown_route_variants(&route, &route.variant_images(), &route_images).await;
+    }else if let Some(traffic_split) = &traffic_split {
+        //variants that are gone or changed image lose their load balancer, images still served keep their containers
+        let kept_images: Vec<ObjectId> = mongo_image.into_iter().chain(traffic_split.variants.iter().map(|variant| variant.mongo_image)).collect();
+        let removed_variants: Vec<(String, ObjectId)> = route.variant_images().into_iter()
+            .filter(|(variant, mongo_image)| variant != PRIMARY_VARIANT && !traffic_split.variants.iter().any(|kept_variant| &kept_variant.name == variant && &kept_variant.mongo_image == mongo_image))
+            .collect();
+        ActiveServiceDirectory::teardown_route_variants(&route, &removed_variants, &kept_images).await;
+    }
+
+    match find_route(&o_id).await {
+        Ok(updated_route) => {
+            route_table_utils::refresh_route_table().await;
+            if payload.configs.scaling.is_some() || payload.configs.health_check.is_some() || payload.configs.backend.is_some() || payload.configs.circuit_breaker.is_some() {
+                ActiveServiceDirectory::set_load_balancer_route_config(&updated_route).await;
+            }
+            if let Some(behavior) = behavior {
+                if let Err(err_response) = apply_route_behavior(&updated_route, behavior).await {
+                    return err_response.into_response();
+                }
+            }
+            (StatusCode::OK, Json(RouteResponse::from(updated_route))).into_response()
         },
-        None =>{
-            return (StatusCode::OK, "[PROCESS] Cannot find route").into_response()
+        Err(err_response) => err_response.into_response()
+    }
+}
+
+///shifts the weights of the variants of a container route, the route image receives the weight they leave
+/// 
+/// only the weights change so the containers and load balancers of the variants are kept
+#[debug_handler]
+pub async fn update_route_weights(Path(route_id): Path<String>, payload: Result<Json<UpdateRouteWeightsPayload>, JsonRejection>) -> impl IntoResponse{
+    let o_id = match parse_route_id(&route_id) {
+        Ok(o_id) => o_id,
+        Err(err_response) => return err_response.into_response()
+    };
+    let Json(payload) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return json_error(StatusCode::BAD_REQUEST, rejection.body_text()).into_response()
+    };
+    let route = match find_route(&o_id).await {
+        Ok(route) => route,
+        Err(err_response) => return err_response.into_response()
+    };
+    let mut traffic_split = match route.traffic_split {
+        Some(traffic_split) => traffic_split,
+        None => return json_error(StatusCode::BAD_REQUEST, format!("[ERROR] Route {} has no traffic_split", o_id)).into_response()
+    };
+    if let Some(variant) = payload.weights.keys().find(|variant| !traffic_split.variants.iter().any(|route_variant| &route_variant.name == *variant)) {
+        return json_error(StatusCode::BAD_REQUEST, format!("[ERROR] Route {} has no variant {}", o_id, variant)).into_response();
+    }
+    for variant in traffic_split.variants.iter_mut() {
+        if let Some(weight) = payload.weights.get(&variant.name) {
+            variant.weight = *weight;
         }
     }
+    if let Err(err_response) = validate_variant_weights(traffic_split.variants.iter().map(|variant| &variant.weight)) {
+        return err_response.into_response();
+    }
+    let variants_bson = match mongodb::bson::to_bson(&traffic_split.variants) {
+        Ok(variants_bson) => variants_bson,
+        Err(_) => return json_error(StatusCode::BAD_REQUEST, "[ERROR] weights are invalid").into_response()
+    };
+    match DBCollection::ROUTES.collection::<docker_models::Route>().await.update_one(doc!{
+        "_id": &o_id
+    }, doc!{
+        "$set": { "traffic_split.variants": variants_bson }
+    }, None).await {
+        Ok(_) => {
+            route_table_utils::refresh_route_table().await;
+            println!("[PROCESS] Route {} now sends {}% of its requests to its image", o_id, traffic_split.primary_weight());
+            (StatusCode::OK, Json(TrafficSplitPayload::from(traffic_split))).into_response()
+        },
+        Err(_) => json_error(StatusCode::INTERNAL_SERVER_ERROR, format!("[ERROR] Failed in updating the weights of route {}", o_id)).into_response()
+    }
+}
 
-    
+#[debug_handler]
+pub async fn remove_route(Path(route_id): Path<String>) -> impl IntoResponse{
 
-    //remove in    
-}
\ No newline at end of file
+    let o_id = match parse_route_id(&route_id) {
+        Ok(o_id) => o_id,
+        Err(err_response) => return err_response.into_response()
+    };
+    let route = match find_route(&o_id).await {
+        Ok(route) => route,
+        Err(err_response) => return err_response.into_response()
+    };
+
+    match DBCollection::ROUTES.collection::<docker_models::Route>().await.delete_one(doc!{
+        "_id": &route._id
+    }, None).await {
+        Ok(_res)=>{
+            println!("[PROCESS] Successfully deleted route {} from db", &o_id);
+            route_table_utils::refresh_route_table().await;
+            ActiveServiceDirectory::teardown_route_load_balancer(&route).await;
+            println!("[PROCESS] Successfully removed {} from the router", route.address);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(_)=>{
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, format!("[ERROR] Failed in deleting route {}", o_id)).into_response()
+        }
+    }
+}
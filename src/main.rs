@@ -1,10 +1,9 @@
-use std::{env, net::SocketAddr, path::PathBuf, process::exit};
-use axum_server::{tls_rustls::RustlsConfig};
+use std::{env, net::SocketAddr, process::exit};
 use bollard::Docker;
 use dotenv::dotenv;
 
 use network::app_router;
-use utils::{docker_utils:: DOCKER_CONNECTION, mongodb_utils::DATABASE};
+use utils::{docker_utils::{self, DOCKER_CONNECTION}, health_utils, mongodb_utils::DATABASE, route_table_utils, scaling_utils, tls_utils::{self, SniAcceptor}};
 mod utils;
 mod network;
 mod models;
@@ -25,8 +24,19 @@ async fn main() {
 			},
 		}
 	});
+    if let Some(docker_network) = docker_utils::docker_network() {
+        if let Err(err_string) = docker_utils::create_docker_network(&docker_network).await {
+            println!("{}", err_string);
+            exit(0x0100)
+        }
+    }
     match DATABASE.set(utils::mongodb_utils::connect().await) {
         Ok(_)=>{
+            route_table_utils::refresh_route_table().await;
+            tokio::spawn(route_table_utils::watch_route_changes());
+            tokio::spawn(scaling_utils::autoscale());
+            tokio::spawn(scaling_utils::reap_idle_containers());
+            tokio::spawn(health_utils::check_container_health());
             listen().await;
         },
         Err(_)=>{
@@ -42,22 +52,18 @@ struct Ports {
 }
 async fn listen(){
 
-    match RustlsConfig::from_pem_file(
-    PathBuf::from("./src/keys")
-        .join("orchestrator.crt"),
-        PathBuf::from("./src/keys")
-        .join("orchestrator_pem.pem"),
-    ).await {
+    match tls_utils::rustls_config() {
     Ok(config) => {
         let router = app_router::router().await;
-        let ip = env::var("ADDRESS").unwrap().split(".").into_iter().map(|x| x.parse::<u8>().unwrap()).collect::<Vec<u8>>();
+        let ip = env::var("ADDRESS").unwrap().split(".").map(|x| x.parse::<u8>().unwrap()).collect::<Vec<u8>>();
         let socket_address = [ip[0],ip[1],ip[2],ip[3]]; 
         // run https server
         let addr = SocketAddr::from((socket_address, env::var("PORT").unwrap().parse::<u16>().unwrap()));
         let addr_s = &addr.to_string();
         println!("listening on {}", addr_s);
-        axum_server::bind_rustls(addr, config)
-            .serve(router.into_make_service())
+        axum_server::bind(addr)
+            .acceptor(SniAcceptor::new(config))
+            .serve(router.into_make_service_with_connect_info::<SocketAddr>())
             .await
             .unwrap();
         
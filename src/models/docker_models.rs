@@ -1,3 +1,5 @@
+use std::{collections::HashMap, fmt};
+
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 
@@ -11,31 +13,441 @@ pub struct ImageInsert{
     pub docker_image_id:String
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub enum RouteTypes {
     STATIC,
     CONTAINER
 }
 
-impl ToString for RouteTypes {
-    
-    fn to_string(&self) -> String {
-        match &self {
-            &Self::STATIC => "static".to_string(),
-            &Self::CONTAINER => "container".to_string()
-        } 
+impl fmt::Display for RouteTypes {
+    fn fmt(&self, formatter:&mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::STATIC => formatter.write_str("static"),
+            Self::CONTAINER => formatter.write_str("container")
+        }
+    }
+}
+
+impl RouteTypes {
+    ///returns the [type RouteTypes] matching the stored route_type string
+    pub fn from_route_type(route_type:&str) -> Option<RouteTypes> {
+        match route_type {
+            "static" => Some(RouteTypes::STATIC),
+            "container" => Some(RouteTypes::CONTAINER),
+            _ => None
+        }
+    }
+}
+///where the consistent_hash behavior reads the affinity key of a request from
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AffinitySource {
+    Cookie,
+    Header,
+    ClientIp
+}
+
+///cookie_name is used by cookie sources, header_name by header sources
+/// 
+/// issue_cookie lets the orchestrator set the cookie itself when a request does not have one
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AffinityConfig {
+    pub source: AffinitySource,
+    pub cookie_name: Option<String>,
+    pub header_name: Option<String>,
+    pub issue_cookie: Option<bool>
+}
+
+impl AffinityConfig {
+    pub const DEFAULT_COOKIE_NAME: &'static str = "orchestrator_affinity";
+
+    pub fn cookie_name(&self) -> &str {
+        self.cookie_name.as_deref().unwrap_or(AffinityConfig::DEFAULT_COOKIE_NAME)
+    }
+}
+
+fn default_scale_up_in_flight() -> f64 { 8.0 }
+fn default_scale_down_in_flight() -> f64 { 2.0 }
+fn default_scale_up_cooldown() -> u64 { 30 }
+fn default_scale_down_cooldown() -> u64 { 120 }
+
+///replica bounds and thresholds the autoscaler keeps a container route within
+/// 
+/// thresholds are the average in-flight requests per container,
+/// cooldowns are the seconds to wait after the last scaling action
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ScalingConfig {
+    pub min_replicas: usize,
+    pub max_replicas: usize,
+    #[serde(default = "default_scale_up_in_flight")]
+    pub scale_up_in_flight: f64,
+    #[serde(default = "default_scale_down_in_flight")]
+    pub scale_down_in_flight: f64,
+    #[serde(default = "default_scale_up_cooldown")]
+    pub scale_up_cooldown: u64,
+    #[serde(default = "default_scale_down_cooldown")]
+    pub scale_down_cooldown: u64
+}
+
+///what the idle reaper does with a container that has been idle for longer than the timeout
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IdleAction {
+    Stop,
+    Remove
+}
+
+///timeout is the seconds since the last request or response of a container
+/// 
+/// stopped containers are started again on the next request,
+/// removed containers are recreated on the next request or by the autoscaler
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IdleConfig {
+    pub timeout: u64,
+    pub action: IdleAction
+}
+
+///how requests wait for a container that was just started
+/// 
+/// path switches the probe from tcp to http, tcp probes on published ports can pass early through docker's proxy.
+/// start_timeout is in seconds and defaults to MAX_TIME_RETRY
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ReadinessConfig {
+    pub path: Option<String>,
+    pub start_timeout: Option<u64>
+}
+
+fn default_health_check_interval() -> u64 { 10 }
+fn default_health_check_timeout() -> u64 { 2 }
+fn default_healthy_threshold() -> usize { 2 }
+fn default_unhealthy_threshold() -> usize { 3 }
+fn default_passive_failure_threshold() -> usize { 5 }
+
+///active probes and passive ejection of the containers of a route
+/// 
+/// path switches the probe from tcp to http, interval and timeout are in seconds and an interval of 0 only keeps passive ejection.
+/// passive_failure_threshold is the consecutive 5xx responses or failed connections before a container is ejected
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HealthCheckConfig {
+    pub path: Option<String>,
+    #[serde(default = "default_health_check_interval")]
+    pub interval: u64,
+    #[serde(default = "default_health_check_timeout")]
+    pub timeout: u64,
+    #[serde(default = "default_healthy_threshold")]
+    pub healthy_threshold: usize,
+    #[serde(default = "default_unhealthy_threshold")]
+    pub unhealthy_threshold: usize,
+    #[serde(default = "default_passive_failure_threshold")]
+    pub passive_failure_threshold: usize
+}
+
+fn default_retry_buffer_size() -> usize { 64 * 1024 }
+
+///max_size is the largest request body in bytes a route accepts, larger requests respond with 413
+/// 
+/// request bodies of up to retry_buffer_size bytes are buffered so a failed attempt can be retried,
+/// larger bodies and bodies of unknown size are streamed to the destination and never retried
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BodyConfig {
+    pub max_size: Option<usize>,
+    #[serde(default = "default_retry_buffer_size")]
+    pub retry_buffer_size: usize
+}
+
+impl Default for BodyConfig {
+    fn default() -> Self {
+        BodyConfig { max_size: None, retry_buffer_size: default_retry_buffer_size() }
+    }
+}
+
+///request_timeout is the seconds a request may take including its response body,
+/// event streams, and every response when long_poll is set, are only bound by it until the destination responds.
+/// idle_timeout is the seconds the destination may go without sending a chunk of the response body
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TimeoutConfig {
+    pub request_timeout: Option<u64>,
+    pub idle_timeout: Option<u64>,
+    #[serde(default)]
+    pub long_poll: bool
+}
+
+///failures of a forwarded request that can be retried
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryCondition {
+    ConnectError,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout
+}
+
+fn default_retry_max_attempts() -> usize { 2 }
+fn default_retry_base_delay() -> u64 { 25 }
+fn default_retry_max_delay() -> u64 { 1000 }
+fn default_retry_on() -> Vec<RetryCondition> { vec![RetryCondition::ConnectError] }
+
+///max_attempts includes the first attempt, retries wait base_delay * 2^retry milliseconds capped at max_delay with full jitter
+/// 
+/// only get, head, options, trace, put and delete requests are retried unless retry_non_idempotent is set,
+/// requests with a streamed body are never retried. container routes send retries to another container when there is one
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RetryConfig {
+    #[serde(default = "default_retry_max_attempts")]
+    pub max_attempts: usize,
+    #[serde(default = "default_retry_base_delay")]
+    pub base_delay: u64,
+    #[serde(default = "default_retry_max_delay")]
+    pub max_delay: u64,
+    #[serde(default = "default_retry_on")]
+    pub retry_on: Vec<RetryCondition>,
+    #[serde(default)]
+    pub retry_non_idempotent: bool
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_attempts: default_retry_max_attempts(),
+            base_delay: default_retry_base_delay(),
+            max_delay: default_retry_max_delay(),
+            retry_on: default_retry_on(),
+            retry_non_idempotent: false
+        }
+    }
+}
+
+fn default_circuit_window() -> usize { 20 }
+fn default_circuit_minimum_requests() -> usize { 10 }
+fn default_circuit_failure_rate() -> f64 { 0.5 }
+fn default_circuit_open_duration() -> u64 { 30 }
+fn default_circuit_half_open_probes() -> usize { 1 }
+
+///failure rate breaker applied to every container of a container route
+/// 
+/// a container's circuit opens once failure_rate of its last window requests responded with 5xx or failed to connect,
+/// counting only after minimum_requests. open circuits skip the container for open_duration seconds,
+/// then let half_open_probes requests through which close the circuit when they all succeed
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CircuitBreakerConfig {
+    #[serde(default = "default_circuit_window")]
+    pub window: usize,
+    #[serde(default = "default_circuit_minimum_requests")]
+    pub minimum_requests: usize,
+    #[serde(default = "default_circuit_failure_rate")]
+    pub failure_rate: f64,
+    #[serde(default = "default_circuit_open_duration")]
+    pub open_duration: u64,
+    #[serde(default = "default_circuit_half_open_probes")]
+    pub half_open_probes: usize
+}
+
+fn default_preserve_host() -> bool { true }
+
+///host header sent to the destination, the client's host is kept unless preserve_host is false
+/// 
+/// host replaces the host header with a fixed value and takes precedence over preserve_host,
+/// without either the host of the upstream url is sent
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ProxyHeadersConfig {
+    #[serde(default = "default_preserve_host")]
+    pub preserve_host: bool,
+    pub host: Option<String>
+}
+
+impl Default for ProxyHeadersConfig {
+    fn default() -> Self {
+        ProxyHeadersConfig { preserve_host: default_preserve_host(), host: None }
+    }
+}
+
+///how the path of a request is rewritten before it is forwarded
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PathRewriteMode {
+    Strip,
+    Replace,
+    Regex
+}
+
+///strip removes the matched address from the path and replace swaps it for the prefix of the route,
+/// so a route at /eps/api can be served from / of its containers.
+/// regex replaces the first match of pattern in the path with replacement, which can refer to groups as $1 or $name.
+/// the query is kept as is
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PathRewriteConfig {
+    pub mode: PathRewriteMode,
+    pub pattern: Option<String>,
+    pub replacement: Option<String>
+}
+
+///predicate on a header, query parameter or cookie of the request,
+/// matches the exact value, a regex pattern on the value or only the presence of the name when neither is set
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct ValuePredicate {
+    pub name: String,
+    pub value: Option<String>,
+    pub pattern: Option<String>
+}
+
+///predicates a request must satisfy besides the host and address to be served by the route
+///
+/// routes sharing an address are tried by descending priority, then by the number of predicates
+/// and then by the order they were added
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct RoutePredicates {
+    #[serde(default)]
+    pub methods: Vec<String>,
+    #[serde(default)]
+    pub headers: Vec<ValuePredicate>,
+    #[serde(default)]
+    pub query: Vec<ValuePredicate>,
+    #[serde(default)]
+    pub cookies: Vec<ValuePredicate>,
+    #[serde(default)]
+    pub priority: i32
+}
+
+impl RoutePredicates {
+    ///number of predicates, routes with more of them are more specific
+    pub fn specificity(&self) -> usize {
+        usize::from(!self.methods.is_empty()) + self.headers.len() + self.query.len() + self.cookies.len()
+    }
+}
+
+///name that pins the image of the route itself when a traffic split is set
+pub const PRIMARY_VARIANT: &str = "primary";
+
+///image version of a container route receiving weight percent of its requests
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RouteVariant {
+    pub name: String,
+    pub docker_image_id: String,
+    pub mongo_image: ObjectId,
+    pub weight: u32
+}
+
+///splits the requests of a container route between its image and the images of its variants,
+/// the image of the route receives the weight the variants leave.
+/// requests naming a variant or primary in the pin header or cookie are always served by it
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TrafficSplitConfig {
+    pub variants: Vec<RouteVariant>,
+    pub pin_header: Option<String>,
+    pub pin_cookie: Option<String>
+}
+
+impl TrafficSplitConfig {
+    ///weight left for the image of the route
+    pub fn primary_weight(&self) -> u32 {
+        100_u32.saturating_sub(self.variants.iter().map(|variant| variant.weight).sum())
     }
 }
+
+///protocol spoken to the containers or static upstream of a route, h2c is http/2 without tls
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendScheme {
+    Http,
+    #[default]
+    Https,
+    H2c
+}
+
+impl BackendScheme {
+    pub fn url_scheme(&self) -> &'static str {
+        match self {
+            BackendScheme::Https => "https",
+            BackendScheme::Http | BackendScheme::H2c => "http"
+        }
+    }
+}
+
+///scheme and tls settings used to connect to the containers or static upstream of a route
+/// 
+/// certificates are only verified when verify is set or a ca_bundle or pinned_cert is given.
+/// ca_bundle and client_cert are paths to pem files, client_cert holds the certificate chain and private key used for mtls.
+/// pinned_cert is the hex sha256 fingerprint of the certificate the destination must present and replaces chain verification.
+/// server_name is sent as sni and checked against the certificate instead of the host of the destination
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct BackendConfig {
+    #[serde(default)]
+    pub scheme: BackendScheme,
+    #[serde(default)]
+    pub verify: bool,
+    pub ca_bundle: Option<String>,
+    pub pinned_cert: Option<String>,
+    pub server_name: Option<String>,
+    pub client_cert: Option<String>
+}
+
+///docker restart policies a container route can apply
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartPolicyName {
+    No,
+    Always,
+    UnlessStopped,
+    OnFailure
+}
+
+///max_retries is only used by the on_failure policy
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RestartPolicyConfig {
+    pub name: RestartPolicyName,
+    pub max_retries: Option<i64>
+}
+
+///run configuration applied to every container created for a container route
+/// 
+/// binds are host_path:container_path[:options] and volumes are the container paths of anonymous volumes.
+/// memory is in bytes and cpus is the number of cpus the container may use, fractions allowed
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ContainerTemplate {
+    pub env: Option<HashMap<String, String>>,
+    pub binds: Option<Vec<String>>,
+    pub volumes: Option<Vec<String>>,
+    pub memory: Option<i64>,
+    pub cpus: Option<f64>,
+    pub entrypoint: Option<Vec<String>>,
+    pub cmd: Option<Vec<String>>,
+    pub user: Option<String>,
+    pub working_dir: Option<String>,
+    pub labels: Option<HashMap<String, String>>,
+    pub restart_policy: Option<RestartPolicyConfig>
+}
+
 ///exposed port will be used differently depending on route_type
 /// 
-/// static routes will use it directly as is
+/// static routes will use it directly as is when no upstream is given
 /// container routes will use it as a setting for creating containers
+/// 
+/// upstream is only used by static routes as the host:port or url of the forwarded service,
+/// container is only used by container routes
 #[derive(Serialize)]
 pub struct RouteInsert{
     pub mongo_image:Option<ObjectId>,
     pub address: String,
     pub exposed_port: String,
     pub route_type:String,
-    pub prefix:Option<String>
+    pub prefix:Option<String>,
+    pub upstream:Option<String>,
+    pub affinity:Option<AffinityConfig>,
+    pub scaling:Option<ScalingConfig>,
+    pub idle:Option<IdleConfig>,
+    pub readiness:Option<ReadinessConfig>,
+    pub health_check:Option<HealthCheckConfig>,
+    pub container:Option<ContainerTemplate>,
+    pub body:Option<BodyConfig>,
+    pub timeout:Option<TimeoutConfig>,
+    pub backend:Option<BackendConfig>,
+    pub retry:Option<RetryConfig>,
+    pub circuit_breaker:Option<CircuitBreakerConfig>,
+    pub proxy_headers:Option<ProxyHeadersConfig>,
+    pub rewrite:Option<PathRewriteConfig>,
+    pub host:Option<String>,
+    pub predicates:Option<RoutePredicates>,
+    pub traffic_split:Option<TrafficSplitConfig>
 }
 
 
@@ -46,7 +458,52 @@ pub struct Route {
     pub mongo_image: Option<ObjectId>,
     pub address: String,
     pub exposed_port: String, //exposed port portrayed in docker container for quick match
-    pub prefix:Option<String>
+    pub route_type: String,
+    pub prefix:Option<String>,
+    pub upstream:Option<String>,
+    pub affinity:Option<AffinityConfig>,
+    pub scaling:Option<ScalingConfig>,
+    pub idle:Option<IdleConfig>,
+    pub readiness:Option<ReadinessConfig>,
+    pub health_check:Option<HealthCheckConfig>,
+    pub container:Option<ContainerTemplate>,
+    pub body:Option<BodyConfig>,
+    pub timeout:Option<TimeoutConfig>,
+    pub backend:Option<BackendConfig>,
+    pub retry:Option<RetryConfig>,
+    pub circuit_breaker:Option<CircuitBreakerConfig>,
+    pub proxy_headers:Option<ProxyHeadersConfig>,
+    pub rewrite:Option<PathRewriteConfig>,
+    pub host:Option<String>,
+    pub predicates:Option<RoutePredicates>,
+    pub traffic_split:Option<TrafficSplitConfig>
+}
+
+impl Route {
+    ///returns the key of the in-memory load balancer, routes with a host get one per host and address
+    pub fn load_balancer_key(&self) -> String {
+        match &self.host {
+            Some(host) => format!("{}{}", host, self.address),
+            None => self.address.clone()
+        }
+    }
+
+    ///returns the key of the in-memory load balancer of a variant, the primary image uses the key of the route
+    pub fn variant_load_balancer_key(&self, variant:&str) -> String {
+        if variant == PRIMARY_VARIANT {
+            self.load_balancer_key()
+        }else{
+            format!("{}#{}", self.load_balancer_key(), variant)
+        }
+    }
+
+    ///returns the name and image of the primary image and of every variant of a container route
+    pub fn variant_images(&self) -> Vec<(String, ObjectId)> {
+        let variants = self.traffic_split.iter().flat_map(|traffic_split| traffic_split.variants.iter());
+        self.mongo_image.map(|mongo_image| (PRIMARY_VARIANT.to_string(), mongo_image)).into_iter()
+            .chain(variants.map(|variant| (variant.name.clone(), variant.mongo_image)))
+            .collect()
+    }
 }
 
 #[derive(Deserialize, Serialize)]
@@ -65,6 +522,8 @@ pub struct LoadBalancerInsert {
     pub containers: Vec<String>
 }
 
+//mirrors the stored container, not every field is read by the orchestrator
+#[allow(dead_code)]
 #[derive(Deserialize)]
 pub struct Container {
     pub _id: ObjectId,
@@ -75,12 +534,17 @@ pub struct Container {
     pub last_response: Option<String>,
     pub time_requested: Option<i64>,
     pub time_responded: Option<i64>,
-    pub is_detached:Option<bool>
+    pub is_detached:Option<bool>,
+    pub weight:Option<usize>,
+    pub network:Option<String>,
+    pub network_ip:Option<String> //set when the container is started on the network
 }
+///network is the docker network the container is attached to, public_port is then the exposed_port instead of a host port
 #[derive(Serialize,Deserialize)]
 pub struct ContainerInsert {
     pub mongo_image_reference:ObjectId,
     pub container_id:String,
     pub public_port:usize,
+    pub network:Option<String>,
 }
 
@@ -1,12 +1,13 @@
-use std::{collections::HashMap, str::FromStr, sync::{Arc, OnceLock}};
+use std::{collections::HashMap, str::FromStr, sync::{Arc, OnceLock}, time::{Duration, Instant}};
 
 
 use axum::response::IntoResponse;
 use bollard::container::ListContainersOptions;
 use hyper::StatusCode;
 use mongodb::bson::{doc, oid::ObjectId};
+use serde::Serialize;
 use tokio::sync::Mutex;
-use crate::{models::docker_models::{self, Route}, utils::{docker_utils::{self, create_container_instance, create_container_instance_by_load_balancer_key, try_start_container, verify_docker_containers, LoadBalancerBehavior, DOCKER_CONNECTION}, mongodb_utils::{self, DBCollection}}};
+use crate::{models::docker_models::{self, BackendConfig, CircuitBreakerConfig, HealthCheckConfig, Route, ScalingConfig}, utils::{circuit_breaker_utils::{CircuitBreaker, CircuitState}, docker_utils::{self, create_container_instance_by_load_balancer_key, try_start_container, verify_docker_containers, DOCKER_CONNECTION}, health_utils::ContainerHealth, load_balancer_utils::{next_latency_ewma, ContainerLoad, LoadBalancerBehavior, LoadBalancingStrategy}, mongodb_utils::DBCollection}};
 
 
 
@@ -14,19 +15,50 @@ use crate::{models::docker_models::{self, Route}, utils::{docker_utils::{self, c
 pub struct LoadBalancer {
     pub id: String, //mongo_db_load_balancer_instance
     pub address: String,
-    pub head: Arc<Mutex<usize>>,
+    pub route_id: ObjectId, //route whose exposed port and container template the containers are created with
     pub behavior: LoadBalancerBehavior,
+    pub strategy: Arc<Mutex<Box<dyn LoadBalancingStrategy>>>,
     pub containers: Arc<Mutex<Vec<String>>>, //docker_container_id_instances
     pub validated: Arc<Mutex<bool>>, //initially false to let the program know if the containers are checked
-    pub automatic_container_instancing: Arc<Mutex<bool>>
+    pub automatic_container_instancing: Arc<Mutex<bool>>, //lets the autoscaler manage the container count
+    pub scaling: Arc<Mutex<Option<ScalingConfig>>>,
+    pub last_scaled_at: Arc<Mutex<Option<Instant>>>,
+    pub health_check: Arc<Mutex<Option<HealthCheckConfig>>>,
+    pub last_health_check_at: Arc<Mutex<Option<Instant>>>,
+    pub backend: Arc<Mutex<Option<BackendConfig>>>, //scheme and tls settings the health checks connect with
+    pub circuit_breaker: Arc<Mutex<Option<CircuitBreakerConfig>>>,
+    pub circuit_state: Arc<Mutex<CircuitState>> //open while the circuit of every container is open
 }
 
 pub struct Container {
     pub id: String, //references the mongo_db_id_instance
     pub container_id:String, //references the docker_container_id_instance
     pub public_port: usize,
+    pub network_ip: Option<String>, //ip on DOCKER_NETWORK, set when the container is started
+    #[allow(dead_code)]
     pub last_accepted_request: Option<String>,
-    pub last_replied_request: Option<String>
+    #[allow(dead_code)]
+    pub last_replied_request: Option<String>,
+    pub in_flight: usize, //requests currently forwarded to the container
+    pub weight: usize, //used by weighted behaviors
+    pub latency_ewma: Option<f64>, //milliseconds
+    pub health: ContainerHealth,
+    pub consecutive_successes: usize, //passed health checks and requests since the last failure
+    pub consecutive_failures: usize, //failed health checks and requests since the last success
+    pub circuit_breaker: CircuitBreaker
+}
+
+///state of a container exposed through the api, containers that are not loaded in memory only have an id
+#[derive(Debug, Default, Serialize)]
+pub struct ContainerStatus {
+    pub container_id: String,
+    pub public_port: Option<usize>,
+    pub health: Option<ContainerHealth>,
+    pub in_flight: usize,
+    pub weight: Option<usize>,
+    pub latency_ewma: Option<f64>,
+    pub consecutive_failures: usize,
+    pub circuit: Option<CircuitState>
 }
 
 
@@ -37,16 +69,28 @@ pub struct ActiveServiceDirectory {}
 
 impl ActiveServiceDirectory{
     /// returns index of type [type String] of the generated load_balancer
-    pub async fn create_load_balancer(id:String, address:String, behavior: LoadBalancerBehavior, containers:Vec<String>, automatic_container_instancing:Option<bool>)-> String{
+    /// 
+    /// automatic container instancing is enabled when the route has a scaling config
+    pub async fn create_load_balancer(id:String, load_balancer_key:String, route:&Route, behavior: LoadBalancerBehavior, containers:Vec<String>)-> String{
         let mutex = LOAD_BALANCERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new())));
+        let address = load_balancer_key;
+        let scaling = route.scaling.clone();
         let new_load_balancer = LoadBalancer{
             id, //mongo_db_reference
             address: address.clone(),
-            head:Arc::new(Mutex::new(0)),
+            route_id: route._id,
+            strategy:Arc::new(Mutex::new(behavior.strategy())),
             behavior,
             containers : Arc::new(Mutex::new(containers)), //docker_container_id
             validated: Arc::new(Mutex::new(false)),
-            automatic_container_instancing:Arc::new(Mutex::new(automatic_container_instancing.unwrap_or_else(||false))),
+            automatic_container_instancing:Arc::new(Mutex::new(scaling.is_some())),
+            scaling: Arc::new(Mutex::new(scaling)),
+            last_scaled_at: Arc::new(Mutex::new(None)),
+            health_check: Arc::new(Mutex::new(route.health_check.clone())),
+            last_health_check_at: Arc::new(Mutex::new(None)),
+            backend: Arc::new(Mutex::new(route.backend.clone())),
+            circuit_breaker: Arc::new(Mutex::new(route.circuit_breaker.clone())),
+            circuit_state: Arc::new(Mutex::new(CircuitState::Closed)),
         };
         let mut guard = mutex.lock().await;
         guard.insert(address.clone(), new_load_balancer);
@@ -67,59 +111,164 @@ impl ActiveServiceDirectory{
     }
 
     pub async fn remove_load_balancer(load_balancer_key:&String) -> Option<LoadBalancer>{
-        let mut load_balancers_mutex = LOAD_BALANCERS.get().unwrap().lock().await;
-        let load_balancer_value =  load_balancers_mutex.remove(load_balancer_key);
-        return load_balancer_value; 
+        let mut load_balancers_mutex = LOAD_BALANCERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        load_balancers_mutex.remove(load_balancer_key)
+    }
+
+    ///removes the in-memory load balancers of the route and of its variants
+    /// 
+    /// when no other route references an image, its docker containers, container records and load balancer record are removed as well
+    pub async fn teardown_route_load_balancer(route:&Route){
+        if ActiveServiceDirectory::remove_load_balancer(&route.load_balancer_key()).await.is_some() {
+            println!("[PROCESS] Removed load balancer {} from internal memory", route.load_balancer_key());
+        }
+        ActiveServiceDirectory::teardown_route_variants(route, &route.variant_images(), &[]).await;
+    }
+
+    ///removes the in-memory load balancers of the variants of the route
+    /// 
+    /// the containers of the variant images are removed unless the image is kept or referenced by another route
+    pub async fn teardown_route_variants(route:&Route, variants:&[(String, ObjectId)], kept_images:&[ObjectId]){
+        for (variant, mongo_image) in variants {
+            let load_balancer_key = route.variant_load_balancer_key(variant);
+            if ActiveServiceDirectory::remove_load_balancer(&load_balancer_key).await.is_some() {
+                println!("[PROCESS] Removed load balancer {} from internal memory", load_balancer_key);
+            }
+            if !kept_images.contains(mongo_image) {
+                ActiveServiceDirectory::remove_image_containers(route, mongo_image).await;
+            }
+        }
+    }
+
+    async fn remove_image_containers(route:&Route, mongo_image:&ObjectId){
+        let other_route_count = DBCollection::ROUTES.collection::<Route>().await.count_documents(doc!{
+            "$or": [
+                { "mongo_image": mongo_image },
+                { "traffic_split.variants.mongo_image": mongo_image }
+            ],
+            "_id": { "$ne": route._id }
+        }, None).await.unwrap_or(0);
+        if other_route_count > 0 {
+            println!("[PROCESS] Image {} is still used by other routes, keeping its containers", mongo_image);
+            return;
+        }
+        let load_balancer_collection = DBCollection::LOADBALANCERS.collection::<docker_models::LoadBalancer>().await;
+        let mut cursor = match load_balancer_collection.find(doc!{ "mongo_image_reference": mongo_image }, None).await {
+            Ok(cursor) => cursor,
+            Err(_) => {
+                println!("[ERROR] Cannot fetch load balancers of image {}", mongo_image);
+                return;
+            }
+        };
+        let containers_mutex = CONTAINERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new())));
+        while let Ok(true) = cursor.advance().await {
+            let mongo_load_balancer = match cursor.deserialize_current() {
+                Ok(mongo_load_balancer) => mongo_load_balancer,
+                Err(_) => continue
+            };
+            for docker_container_id in mongo_load_balancer.containers.iter() {
+                docker_utils::remove_docker_container(docker_container_id).await;
+                containers_mutex.lock().await.remove(docker_container_id);
+                let _ = DBCollection::CONTAINERS.collection::<docker_models::Container>().await.delete_one(doc!{
+                    "container_id": docker_container_id
+                }, None).await;
+            }
+            let _ = load_balancer_collection.delete_one(doc!{ "_id": mongo_load_balancer._id }, None).await;
+            println!("[PROCESS] Removed load balancer {} and its containers", mongo_load_balancer._id);
+        }
     }
     
+    ///swaps the behavior of the in-memory load balancer, the selection state starts fresh
+    pub async fn set_load_balancer_behavior(load_balancer_key:&String, behavior:LoadBalancerBehavior){
+        let mut load_balancers_mutex = LOAD_BALANCERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        if let Some(load_balancer) = load_balancers_mutex.get_mut(load_balancer_key) {
+            load_balancer.behavior = behavior;
+            *load_balancer.strategy.lock().await = behavior.strategy();
+            println!("[PROCESS] Load balancer {} now uses {}", load_balancer_key, behavior);
+        }
+    }
+
+    ///replaces the scaling, health check, backend and circuit breaker config of the in-memory load balancers of the route and its variants with the ones of the route
+    /// 
+    /// automatic container instancing is toggled with the scaling config
+    pub async fn set_load_balancer_route_config(route:&Route){
+        let load_balancers_mutex = LOAD_BALANCERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        let load_balancer_keys = route.variant_images().into_iter().map(|(variant, _)| route.variant_load_balancer_key(&variant));
+        for load_balancer in load_balancer_keys.filter_map(|load_balancer_key| load_balancers_mutex.get(&load_balancer_key)) {
+            *load_balancer.automatic_container_instancing.lock().await = route.scaling.is_some();
+            *load_balancer.scaling.lock().await = route.scaling.clone();
+            *load_balancer.health_check.lock().await = route.health_check.clone();
+            *load_balancer.backend.lock().await = route.backend.clone();
+            *load_balancer.circuit_breaker.lock().await = route.circuit_breaker.clone();
+        }
+    }
+
+    ///removes the container from the load balancer and deletes it from docker
+    pub async fn retire_container(docker_container_id:&String, load_balancer_key:&String){
+        ActiveServiceDirectory::remove_load_balancer_container(docker_container_id, load_balancer_key).await;
+        //removed from docker first, which still reads the address of the in-memory container
+        docker_utils::remove_docker_container(docker_container_id).await;
+        CONTAINERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await.remove(docker_container_id);
+    }
+
     ///a helper function that validates load_balancer_state
     /// also loads data from the database as a way to restore state
-    pub async fn validate_load_balancer_containers(load_balancer_key:String){
+    /// 
+    /// errors when the load balancer or its database entry does not exist
+    pub async fn validate_load_balancer_containers(load_balancer_key:String)->Result<(), String>{
         
-        let load_balancer_mutex = LOAD_BALANCERS.get().unwrap();
+        let load_balancer_mutex = LOAD_BALANCERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new())));
         let guard = load_balancer_mutex.lock().await;
-        let current_load_balancer = &guard.get(&load_balancer_key).unwrap();
+        let current_load_balancer = guard.get(&load_balancer_key).ok_or_else(|| format!("Load balancer {} does not exist", &load_balancer_key))?;
         let mut is_validated_guard = current_load_balancer.validated.lock().await;
-        if *is_validated_guard == false {
+        if !*is_validated_guard {
             println!("[PROCESS] Attempting to validate lb:{}||{}", &load_balancer_key,&current_load_balancer.id);
             
 
-            let load_balancer_id:ObjectId = ObjectId::from_str(current_load_balancer.id.as_str()).unwrap() ;
+            let load_balancer_id:ObjectId = ObjectId::from_str(current_load_balancer.id.as_str()).map_err(|err| err.to_string())?;
             let mongo_lb_entry = DBCollection::LOADBALANCERS.collection::<docker_models::LoadBalancer>().await.find_one(doc!{
                 "_id": load_balancer_id 
-            }, None).await.unwrap().unwrap();
+            }, None).await.map_err(|err| err.to_string())?
+            .ok_or_else(|| format!("Load balancer {} is not stored", &load_balancer_key))?;
             let containers = mongo_lb_entry.containers;
             println!("[PROCESS] Attempting to validate containers:{:#?}", &containers);
             let verified_containers = verify_docker_containers(containers.clone()).await;
             let mut container_guard = current_load_balancer.containers.lock().await;
             DBCollection::LOADBALANCERS.collection::<docker_models::LoadBalancer>().await.find_one_and_update(doc!{
-                "_id" : ObjectId::from_str(&mongo_lb_entry._id.to_hex()).unwrap()
+                "_id" : mongo_lb_entry._id
             }, doc!{
                 "$set": {"containers" : &verified_containers}
-            }, None).await.unwrap().unwrap();
+            }, None).await.map_err(|err| err.to_string())?;
             *is_validated_guard = true;
             *container_guard = verified_containers;
         }
+        Ok(())
     }
 
-    ///
-    pub async fn create_container_instance(mongodb_container_id:String, docker_container_id:String, public_port: usize) -> String{
+    pub async fn create_container_instance(mongodb_container_id:String, docker_container_id:String, public_port: usize, network_ip: Option<String>, weight: usize) -> String{
         let containers= CONTAINERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new())));
         
         let new_container_instance = Container{
             id: mongodb_container_id,
             container_id: docker_container_id.clone(),
-            public_port: public_port,
+            public_port,
+            network_ip,
             last_accepted_request: None,
             last_replied_request: None,
+            in_flight: 0,
+            weight,
+            latency_ewma: None,
+            health: ContainerHealth::Healthy,
+            consecutive_successes: 0,
+            consecutive_failures: 0,
+            circuit_breaker: CircuitBreaker::default(),
         };
         let mut hashmap_mutex = containers.lock().await;
         println!("[PROCESS] Created container instance");
         hashmap_mutex.insert(docker_container_id.clone(), new_container_instance);
         docker_container_id
     }
-    ///
-    pub async fn create_container_instances(docker_container_ids:&Vec<String>){
+    pub async fn create_container_instances(docker_container_ids:&[String]){
 
         for docker_container_id in  docker_container_ids.iter(){
             let container_query_result = DBCollection::CONTAINERS.collection::<docker_models::Container>().await.find_one(doc!{
@@ -127,38 +276,227 @@ impl ActiveServiceDirectory{
             }, None).await;
 
             if let Some(container) = container_query_result.unwrap(){
-                ActiveServiceDirectory::create_container_instance(container._id.to_hex(), container.container_id, container.public_port).await;
+                ActiveServiceDirectory::create_container_instance(container._id.to_hex(), container.container_id, container.public_port, container.network_ip, container.weight.unwrap_or(1)).await;
             }
         };
     }
 
-    pub async fn next_container(load_balancer_key:String)->(String, usize){
+    ///affinity_key pins the request to a container when the behavior supports it,
+    /// excluded_containers are the containers earlier attempts of the request failed on
+    /// 
+    /// errors when the load balancer does not exist or has no container and one cannot be created
+    pub async fn next_container(load_balancer_key:String, affinity_key:Option<String>, excluded_containers:&[String])->Result<(String, usize), String>{
         //check if there is atleast 1 active container
         
-        let current_containers = ActiveServiceDirectory::get_load_balancer_containers(&load_balancer_key).await;
-        if current_containers.len() == 0 {
-            let _create_container_result = create_container_instance_by_load_balancer_key(&load_balancer_key).await;
+        let current_containers = ActiveServiceDirectory::get_load_balancer_containers(&load_balancer_key).await
+            .ok_or_else(|| format!("Load balancer {} does not exist", &load_balancer_key))?;
+        if current_containers.is_empty() {
+            create_container_instance_by_load_balancer_key(&load_balancer_key).await?;
         }
-        //modify head
-        let load_balancer_mutex = LOAD_BALANCERS.get().unwrap().lock().await;
-        let current_load_balancer = load_balancer_mutex.get(&load_balancer_key).unwrap();
-        let mut head_mutex = current_load_balancer.head.lock().await;
-        //using a new container count to reference the container vector just incase it changed
+        let load_balancer_mutex = LOAD_BALANCERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        //the route can be removed while the container is created
+        let current_load_balancer = load_balancer_mutex.get(&load_balancer_key)
+            .ok_or_else(|| format!("Load balancer {} does not exist", &load_balancer_key))?;
+        //using a new container list to reference the container vector just incase it changed
         let container_mutex = current_load_balancer.containers.lock().await;
-        *head_mutex = (*head_mutex + 1 ) % container_mutex.len();
-        let next_container_docker_id = container_mutex[*head_mutex].clone();
+        if container_mutex.is_empty() {
+            return Err(format!("Load balancer {} has no container", &load_balancer_key));
+        }
+        let candidates = ActiveServiceDirectory::get_container_loads(&container_mutex).await;
+        //containers with an open circuit are skipped, the route fails fast when every circuit is open
+        let circuit_breaker = current_load_balancer.circuit_breaker.lock().await.clone();
+        let admitted_indexes: Vec<usize> = match &circuit_breaker {
+            Some(circuit_breaker) => ActiveServiceDirectory::get_container_admissions(&container_mutex, circuit_breaker).await
+                .into_iter().enumerate().filter(|(_, is_admitted)| *is_admitted).map(|(index, _)| index).collect(),
+            None => (0..candidates.len()).collect()
+        };
+        let route_circuit = if admitted_indexes.is_empty() { CircuitState::Open } else { CircuitState::Closed };
+        let mut circuit_state = current_load_balancer.circuit_state.lock().await;
+        if *circuit_state != route_circuit {
+            println!("[PROCESS] Circuit of {} is {:?}", &load_balancer_key, route_circuit);
+            *circuit_state = route_circuit;
+        }
+        drop(circuit_state);
+        if admitted_indexes.is_empty() {
+            return Err(format!("The circuit of every container of {} is open", &load_balancer_key));
+        }
+        //ejected containers are only selected when every container is ejected,
+        //excluded containers only when no other healthy container is left
+        let healthy_indexes: Vec<usize> = admitted_indexes.iter().copied().filter(|index| !candidates[*index].is_ejected).collect();
+        let failover_indexes: Vec<usize> = healthy_indexes.iter().copied().filter(|index| !excluded_containers.contains(&candidates[*index].container_id)).collect();
+        let selectable_indexes = [failover_indexes, healthy_indexes, admitted_indexes].into_iter().find(|indexes| !indexes.is_empty()).unwrap_or_default();
+        let selectable_candidates: Vec<ContainerLoad> = selectable_indexes.iter().map(|index| candidates[*index].clone()).collect();
+        let next_index = current_load_balancer.strategy.lock().await.select(&selectable_candidates, affinity_key.as_deref()).map(|index| selectable_indexes[index]).unwrap_or(0);
+        let next_container_docker_id = container_mutex[next_index].clone();
+        drop(container_mutex);
+        drop(load_balancer_mutex);
         let container = DBCollection::CONTAINERS.collection::<docker_models::Container>().await.find_one(doc!{
             "container_id": &next_container_docker_id
-        }, None).await.unwrap().unwrap();
-        (container.container_id, container.public_port)
+        }, None).await.map_err(|err| err.to_string())?
+        .ok_or_else(|| format!("Container {} is not stored", &next_container_docker_id))?;
+        Ok((container.container_id, container.public_port))
     }
     
-    pub async fn get_load_balancer_containers(load_balancer_key:&String)->Vec<String>{
-        let load_balancer_mutex = LOAD_BALANCERS.get().unwrap().lock().await;
-        let current_load_balancer = &load_balancer_mutex.get(&load_balancer_key.clone()).unwrap();
-        let current_containers = current_load_balancer.containers.lock().await;
-        let containers = current_containers.iter().map(|x| x.clone()).collect::<Vec<String>>();
-        return containers
+    ///returns the load snapshot of the containers in the same order, unknown containers have the default load
+    pub async fn get_container_loads(docker_container_ids:&[String]) -> Vec<ContainerLoad>{
+        let containers_mutex = CONTAINERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        docker_container_ids.iter().map(|docker_container_id| {
+            match containers_mutex.get(docker_container_id) {
+                Some(container) => ContainerLoad {
+                    container_id: docker_container_id.clone(),
+                    in_flight: container.in_flight,
+                    weight: container.weight,
+                    latency_ewma: container.latency_ewma,
+                    is_ejected: container.health == ContainerHealth::Unhealthy
+                },
+                None => ContainerLoad { container_id: docker_container_id.clone(), weight: 1, ..Default::default() }
+            }
+        }).collect()
+    }
+
+    ///marks a request as in-flight on the container
+    pub async fn begin_container_request(docker_container_id:&String){
+        let mut containers_mutex = CONTAINERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        if let Some(container) = containers_mutex.get_mut(docker_container_id) {
+            container.in_flight += 1;
+        }
+    }
+
+    ///releases an in-flight request of the container and records its latency
+    pub async fn end_container_request(docker_container_id:&String, latency:Duration){
+        let mut containers_mutex = CONTAINERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        if let Some(container) = containers_mutex.get_mut(docker_container_id) {
+            container.in_flight = container.in_flight.saturating_sub(1);
+            container.latency_ewma = Some(next_latency_ewma(container.latency_ewma, latency.as_secs_f64() * 1000.0));
+        }
+    }
+
+    ///records a passed or failed health check or request of the container
+    /// 
+    /// returns the new health only when the container crossed the healthy or unhealthy threshold
+    pub async fn record_container_health(docker_container_id:&String, is_healthy:bool, healthy_threshold:usize, unhealthy_threshold:usize) -> Option<ContainerHealth>{
+        let mut containers_mutex = CONTAINERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        let container = containers_mutex.get_mut(docker_container_id)?;
+        if is_healthy {
+            container.consecutive_successes += 1;
+            container.consecutive_failures = 0;
+            if container.health == ContainerHealth::Unhealthy && container.consecutive_successes >= healthy_threshold {
+                container.health = ContainerHealth::Healthy;
+                return Some(ContainerHealth::Healthy);
+            }
+        }else{
+            container.consecutive_failures += 1;
+            container.consecutive_successes = 0;
+            if container.health == ContainerHealth::Healthy && container.consecutive_failures >= unhealthy_threshold {
+                container.health = ContainerHealth::Unhealthy;
+                return Some(ContainerHealth::Unhealthy);
+            }
+        }
+        None
+    }
+
+    ///sets the health of the container and starts counting checks from zero
+    pub async fn set_container_health(docker_container_id:&String, health:ContainerHealth){
+        let mut containers_mutex = CONTAINERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        if let Some(container) = containers_mutex.get_mut(docker_container_id) {
+            container.health = health;
+            container.consecutive_successes = 0;
+            container.consecutive_failures = 0;
+        }
+    }
+
+    ///returns whether the circuit of each container lets a request through, in the same order
+    pub async fn get_container_admissions(docker_container_ids:&[String], circuit_breaker:&CircuitBreakerConfig) -> Vec<bool>{
+        let containers_mutex = CONTAINERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        docker_container_ids.iter().map(|docker_container_id| {
+            containers_mutex.get(docker_container_id).is_none_or(|container| container.circuit_breaker.allows_request(circuit_breaker))
+        }).collect()
+    }
+
+    ///lets a request through the circuit of the container, an open circuit past its open_duration becomes half-open
+    pub async fn acquire_container_circuit(docker_container_id:&String, circuit_breaker:&CircuitBreakerConfig){
+        let mut containers_mutex = CONTAINERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        if let Some(container) = containers_mutex.get_mut(docker_container_id) {
+            if let Some(circuit_state) = container.circuit_breaker.acquire(circuit_breaker) {
+                println!("[PROCESS] Circuit of container {} is {:?}", docker_container_id, circuit_state);
+            }
+        }
+    }
+
+    ///gives back a probe of the circuit of the container whose request never got a response of the container
+    pub async fn release_container_circuit(docker_container_id:&String){
+        let mut containers_mutex = CONTAINERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        if let Some(container) = containers_mutex.get_mut(docker_container_id) {
+            container.circuit_breaker.release();
+        }
+    }
+
+    ///records the outcome of a request in the circuit of the container and returns the new state when it changed
+    pub async fn record_container_circuit(docker_container_id:&String, is_success:bool, circuit_breaker:&CircuitBreakerConfig) -> Option<CircuitState>{
+        let mut containers_mutex = CONTAINERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        let container = containers_mutex.get_mut(docker_container_id)?;
+        container.circuit_breaker.record(is_success, circuit_breaker)
+    }
+
+    ///returns the route level circuit of the load balancer, open while the circuit of every container is open
+    pub async fn get_load_balancer_circuit(load_balancer_key:&String) -> Option<CircuitState>{
+        let load_balancers_mutex = LOAD_BALANCERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        let load_balancer = load_balancers_mutex.get(load_balancer_key)?;
+        let circuit_state = *load_balancer.circuit_state.lock().await;
+        Some(circuit_state)
+    }
+
+    pub async fn get_container_public_port(docker_container_id:&String) -> Option<usize>{
+        let containers_mutex = CONTAINERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        containers_mutex.get(docker_container_id).map(|container| container.public_port)
+    }
+
+    pub async fn get_container_network_ip(docker_container_id:&String) -> Option<String>{
+        let containers_mutex = CONTAINERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        containers_mutex.get(docker_container_id).and_then(|container| container.network_ip.clone())
+    }
+
+    pub async fn set_container_network_ip(docker_container_id:&String, network_ip:Option<String>){
+        let mut containers_mutex = CONTAINERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        if let Some(container) = containers_mutex.get_mut(docker_container_id) {
+            container.network_ip = network_ip;
+        }
+    }
+
+    ///returns the status of the containers in the same order
+    pub async fn get_container_statuses(docker_container_ids:&[String]) -> Vec<ContainerStatus>{
+        let containers_mutex = CONTAINERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        docker_container_ids.iter().map(|docker_container_id| {
+            match containers_mutex.get(docker_container_id) {
+                Some(container) => ContainerStatus {
+                    container_id: docker_container_id.clone(),
+                    public_port: Some(container.public_port),
+                    health: Some(container.health),
+                    in_flight: container.in_flight,
+                    weight: Some(container.weight),
+                    latency_ewma: container.latency_ewma,
+                    consecutive_failures: container.consecutive_failures,
+                    circuit: Some(container.circuit_breaker.state())
+                },
+                None => ContainerStatus { container_id: docker_container_id.clone(), ..Default::default() }
+            }
+        }).collect()
+    }
+
+    ///sets the weight of the container used by weighted behaviors
+    pub async fn set_container_weight(docker_container_id:&String, weight:usize){
+        let mut containers_mutex = CONTAINERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        if let Some(container) = containers_mutex.get_mut(docker_container_id) {
+            container.weight = weight;
+        }
+    }
+
+    ///returns None when the load balancer does not exist, e.g. after its route was removed
+    pub async fn get_load_balancer_containers(load_balancer_key:&str)->Option<Vec<String>>{
+        let load_balancer_mutex = LOAD_BALANCERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        let current_load_balancer = load_balancer_mutex.get(load_balancer_key)?;
+        let current_containers = current_load_balancer.containers.lock().await.clone();
+        Some(current_containers)
     }
     
     pub async fn update_load_balancer_validation(load_balancer_key:String, validation_value:bool){
@@ -171,9 +509,12 @@ impl ActiveServiceDirectory{
 
     pub async fn remove_load_balancer_container(docker_container_id: &String, load_balancer_key:&String){
 
-        let new_containers = docker_utils::remove_container_instance(load_balancer_key, docker_container_id).await;
+        let new_containers = match docker_utils::remove_container_instance(load_balancer_key, docker_container_id).await {
+            Some(new_containers) => new_containers,
+            None => return
+        };
 
-        let load_balancer_mutex = LOAD_BALANCERS.get().unwrap().lock().await;
+        let load_balancer_mutex = LOAD_BALANCERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
         match load_balancer_mutex.get(load_balancer_key) {
            Some( load_balancer )=>{
                 let mut containers_mutex = load_balancer.containers.lock().await;
@@ -199,15 +540,15 @@ impl ActiveServiceDirectory{
         };
         let docker = DOCKER_CONNECTION.get().unwrap();
         let container_list = docker.list_containers(Some(list_container_options)).await.unwrap();
-        if container_list.len() > 0 {
+        if !container_list.is_empty() {
             println!("[PROCESS] Container exists but cannot be started");
-            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Cannot create a connection to the destination").into_response())
+            Err((StatusCode::SERVICE_UNAVAILABLE, "Cannot create a connection to the destination").into_response())
         }else{ //cannot find container
             ActiveServiceDirectory::remove_load_balancer_container(docker_container_id, load_balancer_key).await;
             
             let created_container = docker_utils::create_container_instance_by_load_balancer_key(load_balancer_key).await;
             match created_container {
-                Some(container) =>{
+                Ok(container) =>{
                     match try_start_container(&container.container_id).await {
                         Ok(_)=>{
                             println!("[PROCESS] New container via correction started");
@@ -215,12 +556,13 @@ impl ActiveServiceDirectory{
                         },
                         Err(err_string)=>{
                             println!("[ERROR] {}", err_string);
-                            Err((StatusCode::INTERNAL_SERVER_ERROR, "[ERROR] Failed to start a container inside on re-attempt").into_response())
+                            Err((StatusCode::SERVICE_UNAVAILABLE, "[ERROR] Failed to start a container inside on re-attempt").into_response())
                         }
                     }
                 },
-                None=>{
-                    Err((StatusCode::INTERNAL_SERVER_ERROR, "[ERROR] Failed to create a container instance").into_response())
+                Err(err_string)=>{
+                    println!("[ERROR] {}", err_string);
+                    Err((StatusCode::SERVICE_UNAVAILABLE, format!("[ERROR] Failed to create a container instance: {}", err_string)).into_response())
                 }
             }
             //return(StatusCode::OK).into_response()
@@ -1,6 +1,7 @@
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 
+#[allow(dead_code)]
 #[derive(Deserialize)]
 pub struct Request{
     pub _id: ObjectId,
@@ -10,6 +11,7 @@ pub struct Request{
     pub status_code: String
 }
 
+#[allow(dead_code)]
 #[derive(Serialize)]
 pub struct InsertRequest{
     pub _id: ObjectId,
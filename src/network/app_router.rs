@@ -1,277 +1,732 @@
 
-use std::{env, path::PathBuf, time::UNIX_EPOCH};
+use std::{future::Future, io, net::SocketAddr, pin::Pin, sync::Arc, time::{Duration, Instant}};
 
-use axum::{body::{to_bytes, Body}, extract::Request, response::IntoResponse, routing::{delete, get, patch, post, put}, Json, Router};
-use hyper::{HeaderMap, StatusCode, Uri};
-use mongodb::{bson::{doc, oid::ObjectId}, Database};
+use axum::{body::{to_bytes, Body, HttpBody}, extract::{ConnectInfo, Request}, http::request::Parts, response::{IntoResponse, Response}, routing::{any, get, patch}, Router};
+use bytes::Bytes;
+use http_body_util::{LengthLimitError, Limited};
+use hyper_util::rt::TokioIo;
+use hyper::{http::Extensions, header::{HeaderValue, ALLOW, CONNECTION, CONTENT_TYPE, HOST, SET_COOKIE, UPGRADE}, Method, StatusCode, Uri};
+use mongodb::bson::oid::ObjectId;
+use rand::Rng;
+use regex::Regex;
+use sync_wrapper::SyncStream;
 
-use crate::{handlers::route_handler::remove_route, models::{docker_models::{Container, Image, RouteTypes}, load_balancer_models::ActiveServiceDirectory}, utils::{docker_utils::{get_load_balancer_instances, route_container, set_container_latest_reply, set_container_latest_request, try_start_container}, mongodb_utils::{DBCollection, DATABASE}}};
+use crate::{handlers::{container_handler::update_container, metrics_handler::get_upstream_metrics, route_handler::{add_route, get_route, get_route_circuit, get_route_containers, list_routes, remove_route, update_route, update_route_weights}}, models::{docker_models::{BodyConfig, PathRewriteMode, RetryCondition, RetryConfig, RouteTypes, TimeoutConfig}, load_balancer_models::ActiveServiceDirectory}, utils::{docker_utils::{get_load_balancer_instances, resolve_container_upstream, route_container, set_container_latest_reply, set_container_latest_request, try_start_container}, health_utils::{self, ContainerHealth}, readiness_utils::{self, Readiness}, request_utils, route_table_utils::{route_table, RouteEntry, RouteMatch, RouteParams, RouteRequest}, tls_utils::TlsServerName, traffic_split_utils, upstream_utils::upstream_clients}};
 use crate::models::docker_models::Route;
-use crate::handlers::route_handler::add_route;
 
 pub async fn router()->axum::Router {
     let prefix = "/orchestrator";
+    //the pooled upstream clients live as long as the router
+    upstream_clients();
 
     let router = Router::new()
-        // .route(format!("{prefix}/v1/routes/add", prefix = prefix).as_str(),post(add_route))
-        // .route(format!("{prefix}/v1/routes/remove/:id", prefix = prefix).as_str(), get(remove_route))
-        .route("/*path",
-            get(active_service_discovery)
-            .patch(active_service_discovery)
-            .post(active_service_discovery)
-            .put(active_service_discovery)
-            .delete(active_service_discovery)
-        );
+        .route(format!("{prefix}/v1/routes", prefix = prefix).as_str(), get(list_routes).post(add_route))
+        .route(format!("{prefix}/v1/routes/:id", prefix = prefix).as_str(), get(get_route).patch(update_route).delete(remove_route))
+        .route(format!("{prefix}/v1/routes/:id/containers", prefix = prefix).as_str(), get(get_route_containers))
+        .route(format!("{prefix}/v1/routes/:id/circuit", prefix = prefix).as_str(), get(get_route_circuit))
+        .route(format!("{prefix}/v1/routes/:id/weights", prefix = prefix).as_str(), patch(update_route_weights))
+        .route(format!("{prefix}/v1/containers/:id", prefix = prefix).as_str(), patch(update_container))
+        .route(format!("{prefix}/v1/metrics/upstream", prefix = prefix).as_str(), get(get_upstream_metrics))
+        //every method is routed so method predicates of routes can answer with 405
+        .route("/*path", any(active_service_discovery));
         
-    return router;
+    router
 }
 
 
-pub async fn active_service_discovery(request: Request<Body>) 
+pub async fn active_service_discovery(ConnectInfo(client_address): ConnectInfo<SocketAddr>, mut request: Request<Body>) 
 -> impl IntoResponse
 {   
     println!("[PROCESS] Request: {:#?}", request);
-    let uri = request.uri();
+    //create an id for the request
+    let request_id:String = ObjectId::new().to_hex();
+    let host = request_utils::request_host(request.headers(), request.uri()).map(|host| request_utils::normalize_host(&host));
+    let server_name = request.extensions().get::<TlsServerName>().and_then(|server_name| server_name.0.clone());
+    if let (Some(host), Some(server_name)) = (&host, &server_name) {
+        if host != server_name {
+            //the certificate was chosen for the server name, clients retry other hosts on a connection of their own
+            println!("[ERROR] Host {} does not match the server name {} of the connection", host, server_name);
+            return (StatusCode::MISDIRECTED_REQUEST, "[ERROR] The host does not match the server name of the connection").into_response()
+        }
+    }
+    let route_identifier_result = route_identifier(&RouteRequest {
+        host: host.as_deref().or(server_name.as_deref()),
+        method: request.method(),
+        uri: request.uri(),
+        headers: request.headers()
+    }).await;
+    if let Ok(RouteIdentifierResult::CONTAINER { route, params, entry, .. } | RouteIdentifierResult::STATIC { route, params, entry, .. }) = &route_identifier_result {
+        set_proxy_headers(&mut request, &client_address, route, params, &request_id);
+        //read by the path rewrite when the request is forwarded
+        request.extensions_mut().insert(params.clone());
+        request.extensions_mut().insert(entry.clone());
+    }
     let headers = request.headers();
 
-    let response = match  route_identifier(headers, uri).await {
-        Some(T_RouteIdentifierResult) => {
-			
-				let RouteIdentifierResult {mongo_image_id, docker_image_id, container_path , prefix} = T_RouteIdentifierResult;
-				let load_balancer_key =get_load_balancer_instances(mongo_image_id.clone(), container_path).await;
-				let port_forward_result = port_forward_request(load_balancer_key, request,prefix).await;
-				port_forward_result.into_response()        
-            
+    let mut response = match route_identifier_result {
+        Ok(RouteIdentifierResult::CONTAINER { mongo_image_id, route, .. }) => {
+            let affinity = &route.affinity;
+            let mut affinity_key = affinity.as_ref().and_then(|affinity| request_utils::affinity_key(affinity, headers, &client_address));
+            let mut issued_cookie: Option<HeaderValue> = None;
+            if let (None, Some(affinity)) = (&affinity_key, affinity) {
+                if let Some((affinity_value, set_cookie)) = request_utils::issue_affinity_cookie(affinity) {
+                    affinity_key = Some(affinity_value);
+                    issued_cookie = Some(set_cookie);
+                }
+            }
+            let (variant, variant_image_id) = traffic_split_utils::select_variant(&route, mongo_image_id, headers, affinity_key.as_deref());
+            let load_balancer_key =get_load_balancer_instances(variant_image_id, &route, route.variant_load_balancer_key(&variant)).await;
+            let mut port_forward_result = port_forward_request(load_balancer_key, request, &route, affinity_key, &request_id).await;
+            if let Some(set_cookie) = issued_cookie {
+                port_forward_result.headers_mut().append(SET_COOKIE, set_cookie);
+            }
+            port_forward_result
+        },
+        Ok(RouteIdentifierResult::STATIC { upstream, route, .. }) if request_utils::is_upgrade_request(headers) => {
+            forward_upgrade_request(request, upstream, &route, |_| async {}).await
+        },
+        Ok(RouteIdentifierResult::STATIC { upstream, route, .. }) => {
+            forward_static_request(request, upstream, &route).await
+        },
+        Err(route_response) => route_response
+    };
+    if let Ok(request_id) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(request_utils::X_REQUEST_ID, request_id);
+    }
+    response
+}
+
+///replaces the hop-by-hop headers of the request with the forwarding headers of the proxy and its x-request-id
+/// 
+/// upgrade requests keep their connection and upgrade headers so the destination can switch protocols,
+/// the host header is set by the proxy_headers config of the route and the parameters of the address are sent as x-route-param headers
+fn set_proxy_headers(request:&mut Request, client_address:&SocketAddr, route:&Route, params:&RouteParams, request_id:&str) {
+    let host = request_utils::request_host(request.headers(), request.uri());
+    let headers = request.headers_mut();
+    let upgrade = if request_utils::is_upgrade_request(headers) { headers.get(UPGRADE).cloned() } else { None };
+    request_utils::remove_hop_by_hop_headers(headers);
+    if let Some(upgrade) = upgrade {
+        headers.insert(CONNECTION, HeaderValue::from_static("upgrade"));
+        headers.insert(UPGRADE, upgrade);
+    }
+    //the matched address is the prefix the destination is mounted under once it is stripped or replaced
+    let forwarded_prefix = match route.rewrite.as_ref().map(|rewrite| rewrite.mode) {
+        Some(PathRewriteMode::Strip | PathRewriteMode::Replace) => Some(params.matched_path.trim_end_matches('/')).filter(|matched_path| !matched_path.is_empty()),
+        _ => None
+    };
+    request_utils::set_forwarded_headers(headers, client_address, host.as_deref(), forwarded_prefix);
+    request_utils::set_route_param_headers(headers, &params.values);
+    if let Ok(request_id) = HeaderValue::from_str(request_id) {
+        headers.insert(request_utils::X_REQUEST_ID, request_id);
+    }
+    let proxy_headers = route.proxy_headers.clone().unwrap_or_default();
+    let forwarded_host = match (proxy_headers.host, host) {
+        (Some(host), _) => HeaderValue::from_str(&host).ok(),
+        (None, Some(host)) if proxy_headers.preserve_host => HeaderValue::from_str(&host).ok(),
+        _ => None
+    };
+    match forwarded_host {
+        Some(forwarded_host) => headers.insert(HOST, forwarded_host),
+        //the host of the upstream url is sent instead
+        None => headers.remove(HOST)
+    };
+}
+
+
+#[allow(clippy::upper_case_acronyms)]
+pub enum RouteIdentifierResult {
+    CONTAINER {mongo_image_id:ObjectId, route:Route, params:RouteParams, entry:Arc<RouteEntry>},
+    STATIC {upstream:String, route:Route, params:RouteParams, entry:Arc<RouteEntry>}
+}
+
+///returns the [type RouteIdentifierResult] of the best matching route, or the response when none matches
+/// 
+/// routes are matched against the in-memory route table, the database is not read on the request path.
+/// the host of the request is normalized, routes of the host are preferred over routes without one.
+/// requests matching routes except for their method get a 405 listing the methods of those routes
+pub async fn route_identifier(route_request:&RouteRequest<'_>) -> Result<RouteIdentifierResult, Response>{
+
+    println!("[PROCESS] Searching for routes for:{} {}{}", route_request.method, route_request.host.unwrap_or_default(), route_request.uri.path());
+    let (route_entry, params) = match route_table().await.resolve(route_request) {
+        RouteMatch::Found(route_entry, params) => (route_entry, params),
+        RouteMatch::MethodNotAllowed(allowed_methods) => return Err(method_not_allowed(&allowed_methods)),
+        RouteMatch::NotFound => return Err((StatusCode::NOT_FOUND).into_response())
+    };
+    let matched_route = route_entry.route.clone();
+    match RouteTypes::from_route_type(&matched_route.route_type) {
+        Some(RouteTypes::CONTAINER) => {
+            //routes whose image is not registered cannot be served
+            let mongo_image_id = match (matched_route.mongo_image, &route_entry.docker_image_id) {
+                (Some(mongo_image_id), Some(_)) => mongo_image_id,
+                _ => {
+                    println!("[ERROR] Container route {} has no image", &matched_route.address);
+                    return Err((StatusCode::NOT_FOUND).into_response())
+                }
+            };
+            Ok(RouteIdentifierResult::CONTAINER {
+                mongo_image_id,
+                route: matched_route,
+                params,
+                entry: route_entry
+            })
+        },
+        Some(RouteTypes::STATIC) => {
+            Ok(RouteIdentifierResult::STATIC {
+                upstream: static_upstream(&matched_route),
+                route: matched_route,
+                params,
+                entry: route_entry
+            })
         },
         None => {
-            return (StatusCode::NOT_FOUND).into_response()
+            println!("[ERROR] Route {} has an unknown route_type {}", &matched_route.address, &matched_route.route_type);
+            Err((StatusCode::NOT_FOUND).into_response())
         }
+    }
+}
+
+fn method_not_allowed(allowed_methods:&[Method]) -> Response {
+    let allow = allowed_methods.iter().map(|method| method.as_str()).collect::<Vec<&str>>().join(", ");
+    let mut response = (StatusCode::METHOD_NOT_ALLOWED).into_response();
+    if let Ok(allow) = HeaderValue::from_str(&allow) {
+        response.headers_mut().insert(ALLOW, allow);
+    }
+    response
+}
+
+///returns the base url of a static route
+/// 
+/// uses the stored upstream as is when it has a scheme, otherwise it is treated as host:port reached over the backend scheme.
+/// routes without an upstream fall back to the exposed_port on localhost
+pub fn static_upstream(route:&Route) -> String {
+    let upstream = match &route.upstream {
+        Some(upstream) => upstream.trim_end_matches('/').to_string(),
+        None => format!("localhost:{}", route.exposed_port)
     };
-    return response.into_response();
+    if upstream.contains("://") {
+        upstream
+    }else{
+        let scheme = route.backend.as_ref().map(|backend| backend.scheme).unwrap_or_default();
+        format!("{}://{}", scheme.url_scheme(), upstream)
+    }
 }
 
+pub async fn port_forward_request(load_balancer_key:String, request:Request, route:&Route, affinity_key: Option<String>, request_id:&String) -> Response{
 
-// pub enum RouteIdentifierResult {
-//     CONTAINER {mongo_image_id:ObjectId, docker_image_id:String, container_path:String, prefix:Option<String>},
-//     STATIC {static_port:Option<usize>, prefix:Option<String>}
-// }
-pub struct RouteIdentifierResult {
-	mongo_image_id:ObjectId, docker_image_id:String, container_path:String, prefix:Option<String>
+    if request_utils::is_upgrade_request(request.headers()) {
+        //upgrades are sent once since the client connection is handed over to the container
+        let (docker_container_id, upstream) = match ready_container(&load_balancer_key, route, affinity_key, &[]).await {
+            Ok(ready_container) => ready_container,
+            Err(err_response) => return err_response
+        };
+        acquire_container_circuit(&docker_container_id, route).await;
+        let on_close = track_container_request(&docker_container_id, request_id).await;
+        let forward_result = forward_upgrade_request(request, upstream, route, on_close).await;
+        record_container_response(&load_balancer_key, &docker_container_id, route, container_status(&forward_result)).await;
+        return forward_result
+    }
+    let mut pending_request = match PendingRequest::new(request, route).await {
+        Ok(pending_request) => pending_request,
+        Err(err_response) => return err_response
+    };
+    let mut failed_containers: Vec<String> = Vec::new();
+    loop {
+        let (docker_container_id, upstream) = match ready_container(&load_balancer_key, route, affinity_key.clone(), &failed_containers).await {
+            Ok(ready_container) => ready_container,
+            Err(err_response) => return err_response
+        };
+        match forward_container_request(&mut pending_request, &load_balancer_key, &docker_container_id, upstream, request_id, route).await {
+            ForwardAttempt::Done(response) => return response,
+            ForwardAttempt::Failed(condition, response) => {
+                let Some(retry_delay) = pending_request.retry_delay(condition) else {
+                    return response
+                };
+                println!("[PROCESS] Request to container {} failed with {:?}... Retrying in {}ms", &docker_container_id, condition, retry_delay.as_millis());
+                drop(response);
+                failed_containers.push(docker_container_id);
+                tokio::time::sleep(retry_delay).await;
+            }
+        }
+    }
 }
 
-///returns the [type Option]<mongo_image_id:[type ObjectId], docker_image_id:[type String], container_path:[type String]>
-pub async fn route_identifier(headers:&HeaderMap, uri: &Uri) -> Option<RouteIdentifierResult>{
+///picks a container of the load balancer, starting it when needed, and waits until it is ready
+/// 
+/// returns the docker container id and the upstream base url of the container
+async fn ready_container(load_balancer_key:&String, route:&Route, affinity_key: Option<String>, excluded_containers:&[String]) -> Result<(String, String), Response>{
 
-    let mut uri_string = uri.path_and_query().clone().unwrap().to_string();
-    // }
-    
-    //uri_string = uri.clone();
-    println!("[PROCESS] Searching for routes for:{}", &uri_string);
-    let database: &Database = DATABASE.get().unwrap();
-    let collection_name = DBCollection::ROUTES.to_string();
-    let collection = database.collection::<Route>(collection_name.as_str());
-    let mut cursor: mongodb::Cursor<Route> = collection.find( 
-        doc! {
-            "$expr": {
-                "$eq": [
-                    {
-                        "$indexOfBytes": [
-                            uri_string.clone(),
-                            "$address"
-                           
-                        ]
-                    },
-                    0
-                ]
+    let (docker_container_id, public_port) = match route_container(load_balancer_key.clone(), affinity_key, excluded_containers).await { //literal container id
+        Ok(routed_container) => routed_container,
+        Err(err_string) => {
+            println!("[ERROR] No container for {}: {}", load_balancer_key, err_string);
+            return Err((StatusCode::SERVICE_UNAVAILABLE, format!("[ERROR] {}", err_string)).into_response())
+        }
+    };
+    //try to start the container if not starting
+    let (docker_container_id, public_port, is_started) = match try_start_container(&docker_container_id).await {
+        Ok(is_started)=>{
+            if is_started {
+                println!("[PROCESS] Started container {}", &docker_container_id);
             }
-          }, None).await.unwrap();
-    //#temporary code
-	let mut cursor2: mongodb::Cursor<Route> = collection.find( doc!{ "address" : "/eps/api"}, None).await.unwrap();
-	while cursor2.advance().await.unwrap() {
-        let document_item: Result<Route, mongodb::error::Error> = cursor.deserialize_current();
-        
-        match document_item {
-            Ok(document) => {
-                println!("{:#?}", document);
+            (docker_container_id, public_port, is_started)
+        },
+        Err(_)=>{
+            //cannot start container
+            println!("[ERROR] Unable to start container: {}", load_balancer_key);
+            match ActiveServiceDirectory::start_container_error_correction(&docker_container_id, load_balancer_key).await {
+                Ok((container_id, public_port))=>{
+                    (container_id, public_port, true)
+                },
+                Err(err_response)=>{
+                    ActiveServiceDirectory::update_load_balancer_validation(load_balancer_key.clone(),false).await;
+                    return Err(err_response.into_response())
+                }
             }
-            Err(_) =>{}
-        }        
+        }
+    };
+    let scheme = route.backend.as_ref().map(|backend| backend.scheme).unwrap_or_default();
+    let upstream = match resolve_container_upstream(&docker_container_id, public_port, scheme).await {
+        Ok(upstream) => upstream,
+        Err(err_string) => {
+            println!("[ERROR] {}", err_string);
+            return Err((StatusCode::SERVICE_UNAVAILABLE, "[ERROR] Cannot resolve the address of the destination").into_response())
+        }
+    };
+    match readiness_utils::wait_until_ready(&docker_container_id, &upstream, route.readiness.as_ref(), route.backend.as_ref(), is_started).await {
+        Readiness::Ready => Ok((docker_container_id, upstream)),
+        _ => {
+            println!("[ERROR] Container {} did not become ready within {}s", &docker_container_id, readiness_utils::start_timeout(route.readiness.as_ref()).as_secs());
+            Err((StatusCode::GATEWAY_TIMEOUT, "[ERROR] The destination did not become ready in time").into_response())
+        }
     }
-    let mut route_matches: Vec<Route> = Vec::new();
-    while cursor.advance().await.unwrap() {
-        let document_item: Result<Route, mongodb::error::Error> = cursor.deserialize_current();
-        
-        match document_item {
-            Ok(document) => {
-                route_matches.push(document);
-            }
-            Err(_) =>{}
-        }        
+}
+
+///marks the request as in-flight on the container and returns the close hook that releases it
+/// 
+/// the request stays in-flight until its response body is finished or its upgraded connection closes
+async fn track_container_request(docker_container_id:&String, request_id:&String) -> CloseHook {
+    set_container_latest_request(docker_container_id, request_id).await;
+    ActiveServiceDirectory::begin_container_request(docker_container_id).await;
+    let (closed_container_id, closed_request_id) = (docker_container_id.clone(), request_id.clone());
+    Box::new(move |latency| Box::pin(async move {
+        ActiveServiceDirectory::end_container_request(&closed_container_id, latency).await;
+        set_container_latest_reply(&closed_container_id, &closed_request_id).await;
+    }))
+}
+
+///lets the request through the circuit of the container once it is dispatched to it,
+/// an open circuit past its open_duration becomes half-open and the request its probe
+async fn acquire_container_circuit(docker_container_id:&String, route:&Route) {
+    if let Some(circuit_breaker) = &route.circuit_breaker {
+        ActiveServiceDirectory::acquire_container_circuit(docker_container_id, circuit_breaker).await;
     }
-	
-	
-    println!("[PROCESS] route matches:{}", route_matches.len());
-    if route_matches.len() == 0 { //no matching routes
-        return None
-    }else if route_matches.len() == 1 {
-        let current_route = &route_matches[0];
-		let docker_container_image_result = DBCollection::IMAGES.collection::<Image>().await.find_one(doc!{
-			"_id" : &route_matches[0].mongo_image
-		}, None).await.unwrap();
-		//(mongo_image_id, docker_image_id, container_path)
-		let res: RouteIdentifierResult = RouteIdentifierResult{
-			mongo_image_id: current_route.mongo_image.unwrap(),
-			docker_image_id: docker_container_image_result.unwrap().docker_image_id, //#unwrapping error here
-			container_path: current_route.address.clone(),
-			prefix: current_route.prefix.clone(),
-			
-		};
-		return Some(res);
+}
 
-	}
-    else{
-        return Some(route_resolver(route_matches, &uri_string).await)
+///routes with a health check eject the container after passive_failure_threshold consecutive 5xx responses or failed connections,
+/// routes with a circuit breaker count them towards the failure rate of the container's circuit
+/// 
+/// errors the proxy responds with itself have no status_code, they are not held against the container and only give back its circuit probe
+async fn record_container_response(load_balancer_key:&str, docker_container_id:&String, route:&Route, status_code:Option<StatusCode>) {
+    let Some(status_code) = status_code else {
+        if route.circuit_breaker.is_some() {
+            ActiveServiceDirectory::release_container_circuit(docker_container_id).await;
+        }
+        return;
+    };
+    let is_healthy = !status_code.is_server_error();
+    if let Some(circuit_breaker) = &route.circuit_breaker {
+        if let Some(circuit_state) = ActiveServiceDirectory::record_container_circuit(docker_container_id, is_healthy, circuit_breaker).await {
+            println!("[PROCESS] Circuit of container {} is {:?}", docker_container_id, circuit_state);
+        }
+    }
+    if let Some(health_check) = &route.health_check {
+        if ActiveServiceDirectory::record_container_health(docker_container_id, is_healthy, health_check.healthy_threshold, health_check.passive_failure_threshold).await == Some(ContainerHealth::Unhealthy) {
+            println!("[PROCESS] Ejected container {} after {} failed requests", docker_container_id, health_check.passive_failure_threshold);
+            tokio::spawn(health_utils::recover_container(docker_container_id.clone(), load_balancer_key.to_string(), health_check.clone()));
+        }
     }
-        
 }
-///returns the <mongo_image_id:[type String], docker_image_id:[type String], container_path:[type String]>
-pub async fn route_resolver(route_matches:Vec<Route>, uri:&String) -> RouteIdentifierResult{
-
-    let routes:Vec<Vec<String>> = route_matches.iter().map(|matched_route| {
-        let route:Vec<String> = matched_route.address.split("/").filter(|s| s.to_owned()!="").map(String::from).collect();
-        route
-    }).collect();
-    //need to optimize/gets running per split instead of generally at the end
-    let uri_split:Vec<String> = uri.split("/").filter(|x| x.to_owned() != "").map(|x| {
-        let split_strings = vec!["?", "#"];
-        let mut clone_string = x.to_owned();
-        for split_string in split_strings{
-            clone_string = clone_string.split(split_string).into_iter().collect::<Vec<&str>>()[0].to_string();
+
+///forwards an attempt of the request to the container while tracking it as in-flight for the load balancer
+pub async fn forward_container_request(pending_request:&mut PendingRequest, load_balancer_key:&str, docker_container_id:&String, upstream:String, request_id:&String, route:&Route) -> ForwardAttempt {
+    acquire_container_circuit(docker_container_id, route).await;
+    let on_close = track_container_request(docker_container_id, request_id).await;
+    let forward_attempt = forward_request(pending_request, upstream, route, on_close).await;
+    let (ForwardAttempt::Done(response) | ForwardAttempt::Failed(_, response)) = &forward_attempt;
+    let status_code = container_status(response);
+    record_container_response(load_balancer_key, docker_container_id, route, status_code).await;
+    forward_attempt
+}
+
+///forwards the request of a static route, retries are sent to the same upstream
+pub async fn forward_static_request(request:Request, upstream:String, route:&Route) -> Response {
+    let mut pending_request = match PendingRequest::new(request, route).await {
+        Ok(pending_request) => pending_request,
+        Err(err_response) => return err_response
+    };
+    loop {
+        match forward_request(&mut pending_request, upstream.clone(), route, |_| async {}).await {
+            ForwardAttempt::Done(response) => return response,
+            ForwardAttempt::Failed(condition, response) => {
+                let Some(retry_delay) = pending_request.retry_delay(condition) else {
+                    return response
+                };
+                println!("[PROCESS] Request to {} failed with {:?}... Retrying in {}ms", &upstream, condition, retry_delay.as_millis());
+                drop(response);
+                tokio::time::sleep(retry_delay).await;
+            }
         }
-        let ret_string: String = clone_string.clone();
-        return ret_string
-    }).into_iter().collect();
+    }
+}
 
-    
-    let mut matched_index:usize = 0;
-    let mut max_matches:usize = 0;
-    for (container_index, container_route) in routes.iter().enumerate() {
-        //let mut current_matches:usize = 0;
-        let minimun_matches:usize = container_route.len();
-        if uri_split.starts_with(container_route) && minimun_matches > max_matches{
-            matched_index = container_index;
-            max_matches = minimun_matches
+type CloseHook = Box<dyn FnOnce(Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send>;
+
+///runs the close hook of a forwarded request once it is dropped
+/// 
+/// the guard moves into the response body or the upgraded connection so the hook runs when either finishes,
+/// and still runs when the client goes away while the request is waiting on the destination
+pub struct CloseGuard {
+    on_close: Option<CloseHook>,
+    request_start: Instant,
+    latency: Option<Duration>
+}
+
+impl CloseGuard {
+    pub fn new<F, Fut>(on_close:F) -> CloseGuard
+    where
+        F: FnOnce(Duration) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static
+    {
+        CloseGuard {
+            on_close: Some(Box::new(move |latency| Box::pin(on_close(latency)))),
+            request_start: Instant::now(),
+            latency: None
         }
     }
-    
-    let docker_image_result = DBCollection::IMAGES.collection::<Image>().await.find_one(doc!{
-        "_id" : route_matches[matched_index].mongo_image
-    }, None).await.unwrap().unwrap();
 
+    ///records the time the destination took to respond, the hook receives it instead of the lifetime of the request
+    pub fn responded(&mut self) {
+        self.latency = Some(self.request_start.elapsed());
+    }
+}
 
-	return RouteIdentifierResult{ 
-		mongo_image_id: route_matches[matched_index].mongo_image.clone().unwrap(),
-		docker_image_id: docker_image_result.docker_image_id, 
-		container_path: route_matches[matched_index].address.clone(),
-		prefix: route_matches[matched_index].prefix.clone()
-	}
+impl Drop for CloseGuard {
+    fn drop(&mut self) {
+        if let Some(on_close) = self.on_close.take() {
+            tokio::spawn(on_close(self.latency.unwrap_or_else(|| self.request_start.elapsed())));
+        }
+    }
+}
 
-    // if route_matches[matched_index].route_type == RouteTypes::CONTAINER.to_string(){
-    //     return RouteIdentifierResult::CONTAINER { 
-    //         mongo_image_id: route_matches[matched_index].mongo_image.clone().unwrap(),
-    //         docker_image_id: docker_image_result.docker_image_id, 
-    //         container_path: route_matches[matched_index].address.clone(),
-    //         prefix: route_matches[matched_index].prefix.clone()
-    //     }
-    // }else {
-    //     return RouteIdentifierResult::STATIC { static_port:Some(route_matches[matched_index].exposed_port.clone().parse::<usize>().unwrap()) ,prefix:route_matches[matched_index].prefix.clone() }
-    // }
-    
+///returns a close guard that also releases the request from the stats of the upstream clients
+async fn pooled_close_guard<F, Fut>(upstream:&str, on_close:F) -> CloseGuard
+where
+    F: FnOnce(Duration) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static
+{
+    upstream_clients().begin_request(upstream).await;
+    let pooled_upstream = upstream.to_string();
+    CloseGuard::new(move |latency| async move {
+        upstream_clients().end_request(&pooled_upstream).await;
+        on_close(latency).await;
+    })
 }
 
-pub async fn port_forward_request(load_balancer_key:String, request:Request, prefix: Option<String>) -> impl IntoResponse{
+///forwards an upgrade request and splices the client and destination connections once the destination switches protocols
+/// 
+/// on_close receives the handshake latency and runs when the spliced connection closes or the upgrade is declined
+pub async fn forward_upgrade_request<F, Fut>(mut request:Request, upstream:String, route:&Route, on_close:F) -> Response
+where
+    F: FnOnce(Duration) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static
+{
+    let mut close_guard = pooled_close_guard(&upstream, on_close).await;
+    let client_upgrade = hyper::upgrade::on(&mut request);
+    let (parts, _body) = request.into_parts();
+    let (client, base_url) = match upstream_clients().backend_client(route.backend.as_ref(), &upstream, true).await {
+        Ok(backend_client) => backend_client,
+        Err(err_string) => {
+            println!("[ERROR] {}", err_string);
+            return proxy_error((StatusCode::BAD_GATEWAY, "[ERROR] Cannot create a connection to the destination").into_response())
+        }
+    };
+    let url = format!("{}{}", base_url, rewrite_uri(&parts.uri, route, rewrite_pattern(&parts.extensions), parts.extensions.get::<RouteParams>()));
+    println!("[PROCESS] Upgrading request to : {}", &url);
+    let upstream_response = match client.request(parts.method, &url).headers(parts.headers).send().await {
+        Ok(upstream_response) => upstream_response,
+        Err(error) => {
+            if error.is_connect() {
+                upstream_clients().record_connect_error(&upstream).await;
+            }
+            println!("[ERROR] Failed to upgrade request to {}: {}", &url, error);
+            return (StatusCode::BAD_GATEWAY, "[ERROR] Cannot create a connection to the destination").into_response()
+        }
+    };
+    close_guard.responded();
+    let status_code = StatusCode::from_u16(upstream_response.status().as_u16()).unwrap();
+    let mut headers = upstream_response.headers().clone();
+    if status_code != StatusCode::SWITCHING_PROTOCOLS {
+        //the destination declined the upgrade so its response is relayed as a regular response
+        request_utils::remove_hop_by_hop_headers(&mut headers);
+        return (status_code, headers, response_body(upstream_response, None, None, close_guard)).into_response()
+    }
+    tokio::spawn(async move {
+        upstream_clients().record_upgraded_connection(&upstream, true).await;
+        match (client_upgrade.await, upstream_response.upgrade().await) {
+            (Ok(client_upgraded), Ok(mut upstream_upgraded)) => {
+                let mut client_upgraded = TokioIo::new(client_upgraded);
+                match tokio::io::copy_bidirectional(&mut client_upgraded, &mut upstream_upgraded).await {
+                    Ok((client_bytes, upstream_bytes)) => println!("[PROCESS] Closed upgraded connection to {} after {}/{} bytes", &url, client_bytes, upstream_bytes),
+                    Err(error) => println!("[PROCESS] Upgraded connection to {} closed: {}", &url, error)
+                }
+            },
+            (Err(error), _) => println!("[ERROR] Failed to upgrade the client connection of {}: {}", &url, error),
+            (_, Err(error)) => println!("[ERROR] Failed to upgrade the connection to {}: {}", &url, error)
+        }
+        upstream_clients().record_upgraded_connection(&upstream, false).await;
+        drop(close_guard);
+    });
+    (status_code, headers).into_response()
+}
 
-    let (docker_container_id, public_port) = route_container(load_balancer_key.clone()).await; //literal container id
-    //create an id for the request
-    let request_id:String = ObjectId::new().to_hex();
-    //try to start the container if not starting
-    let forward_request_result = match try_start_container(&docker_container_id).await {
-        Ok(_)=>{
-            println!("[PROCESS] Started container {}", &docker_container_id);
-            let _ = set_container_latest_request(&docker_container_id, &request_id).await;
-            let forward_result = forward_request(request, Some(public_port) ,prefix).await.into_response();
-            let _ = set_container_latest_reply(&docker_container_id, &request_id).await;
-            forward_result.into_response()
-        },
-        Err(_)=>{
-            //cannot start container
-            println!("[ERROR] Unable to start container: {}", &load_balancer_key);
-            match ActiveServiceDirectory::start_container_error_correction(&docker_container_id, &load_balancer_key).await {
-                Ok((container_id, public_port))=>{
-                    let _ = set_container_latest_request(&container_id, &request_id).await;
+///returns true for responses that are streamed for as long as the destination keeps them open,
+/// event streams and every response of routes that opted into long_poll
+fn is_streaming_response(headers:&reqwest::header::HeaderMap, timeout_config:&TimeoutConfig) -> bool {
+    let is_event_stream = headers.get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/event-stream"));
+    is_event_stream || timeout_config.long_poll
+}
 
-                    let forward_result = forward_request(request, Some(public_port),prefix).await.into_response();
+///streams the response body of the destination chunk by chunk as it arrives
+/// 
+/// idle_timeout bounds the wait for each chunk and deadline the whole body, the close guard is dropped once the body ends
+fn response_body(upstream_response:reqwest::Response, idle_timeout:Option<Duration>, deadline:Option<tokio::time::Instant>, close_guard:CloseGuard) -> Body {
+    Body::from_stream(futures_util::stream::unfold(Some((upstream_response, close_guard)), move |state| async move {
+        let (mut upstream_response, close_guard) = state?;
+        let chunk_deadline = match (idle_timeout.map(|idle_timeout| tokio::time::Instant::now() + idle_timeout), deadline) {
+            (Some(idle_deadline), Some(deadline)) => Some(idle_deadline.min(deadline)),
+            (idle_deadline, deadline) => idle_deadline.or(deadline)
+        };
+        let chunk_result = match chunk_deadline {
+            Some(chunk_deadline) => match tokio::time::timeout_at(chunk_deadline, upstream_response.chunk()).await {
+                Ok(chunk_result) => chunk_result.map_err(io::Error::other),
+                Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "the destination stopped sending the response in time"))
+            },
+            None => upstream_response.chunk().await.map_err(io::Error::other)
+        };
+        match chunk_result {
+            Ok(Some(chunk)) => Some((Ok(chunk), Some((upstream_response, close_guard)))),
+            Ok(None) => None,
+            Err(error) => {
+                println!("[ERROR] Stopped streaming the response of {}: {}", upstream_response.url(), error);
+                Some((Err(error), None))
+            }
+        }
+    }))
+}
 
-                    let _  = set_container_latest_reply(&container_id, &request_id).await;
-                    forward_result
-                },
-                Err(err_response)=>{
-                    ActiveServiceDirectory::update_load_balancer_validation(load_balancer_key,false).await;
-                    err_response.into_response()
-                }
+///a request body that is either buffered in full or streamed to the destination
+enum ForwardBody {
+    Buffered(Bytes),
+    Streaming(Option<Body>)
+}
+
+impl ForwardBody {
+    ///buffers bodies of a known size up to retry_buffer_size, larger bodies are streamed behind the max_size limit
+    async fn new(body:Body, body_config:&BodyConfig) -> Result<ForwardBody, Response> {
+        let body_size = body.size_hint().exact();
+        if let (Some(max_size), Some(body_size)) = (body_config.max_size, body_size) {
+            if body_size as usize > max_size {
+                return Err(payload_too_large(max_size));
             }
         }
-    };
-    forward_request_result
+        match body_size {
+            Some(body_size) if body_size as usize <= body_config.retry_buffer_size => match to_bytes(body, body_config.retry_buffer_size).await {
+                Ok(bytes) => Ok(ForwardBody::Buffered(bytes)),
+                Err(_) => Err((StatusCode::BAD_REQUEST, "[ERROR] Cannot read the request body").into_response())
+            },
+            _ => match body_config.max_size {
+                Some(max_size) => Ok(ForwardBody::Streaming(Some(Body::new(Limited::new(body, max_size))))),
+                None => Ok(ForwardBody::Streaming(Some(body)))
+            }
+        }
+    }
+
+    ///returns the body of the next attempt, streamed bodies can only be sent once
+    fn take(&mut self) -> Option<reqwest::Body> {
+        match self {
+            ForwardBody::Buffered(bytes) => Some(reqwest::Body::from(bytes.clone())),
+            ForwardBody::Streaming(body) => body.take().map(|body| reqwest::Body::wrap_stream(SyncStream::new(body.into_data_stream())))
+        }
+    }
+
+    fn is_replayable(&self) -> bool {
+        matches!(self, ForwardBody::Buffered(_))
+    }
 }
 
-pub async fn forward_request(request:Request, public_port:Option<usize>, prefix: Option<String>)
--> impl IntoResponse
+///marks responses the proxy failed a request with before the container could respond
+#[derive(Clone, Copy)]
+struct ProxyError;
+
+fn proxy_error(mut response:Response) -> Response {
+    response.extensions_mut().insert(ProxyError);
+    response
+}
+
+///returns the status of the response unless the proxy failed the request itself
+fn container_status(response:&Response) -> Option<StatusCode> {
+    match response.extensions().get::<ProxyError>() {
+        Some(_) => None,
+        None => Some(response.status())
+    }
+}
+
+fn payload_too_large(max_size:usize) -> Response {
+    (StatusCode::PAYLOAD_TOO_LARGE, format!("[ERROR] The request body exceeds {} bytes", max_size)).into_response()
+}
+
+///returns true when the request failed because its streamed body went over the max_size limit
+fn is_length_limit_error(error:&reqwest::Error) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(error);
+    while let Some(error) = source {
+        if error.is::<LengthLimitError>() {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
+
+///the outcome of an attempt, failed attempts carry the response that is relayed when the request is not retried
+pub enum ForwardAttempt {
+    Done(Response),
+    Failed(RetryCondition, Response)
+}
+
+///a request that is forwarded in one or more attempts under the retry policy of its route
+pub struct PendingRequest {
+    parts: Parts,
+    body: ForwardBody,
+    body_config: BodyConfig,
+    timeout_config: TimeoutConfig,
+    retry_config: RetryConfig,
+    deadline: Option<tokio::time::Instant>,
+    attempts: usize
+}
+
+impl PendingRequest {
+    ///reads the body of the request as its route allows, the request timeout covers every attempt
+    pub async fn new(request:Request, route:&Route) -> Result<PendingRequest, Response> {
+        let timeout_config = route.timeout.clone().unwrap_or_default();
+        let deadline = timeout_config.request_timeout.map(|request_timeout| tokio::time::Instant::now() + Duration::from_secs(request_timeout));
+        let (parts, body) = request.into_parts();
+        let body_config = route.body.clone().unwrap_or_default();
+        let body = ForwardBody::new(body, &body_config).await?;
+        Ok(PendingRequest {
+            parts,
+            body,
+            body_config,
+            timeout_config,
+            retry_config: route.retry.clone().unwrap_or_default(),
+            deadline,
+            attempts: 0
+        })
+    }
+
+    ///returns the backoff before the next attempt or none when the failed attempt cannot be retried
+    /// 
+    /// the backoff grows exponentially from base_delay up to max_delay and a random part of it is waited
+    pub fn retry_delay(&self, condition:RetryCondition) -> Option<Duration> {
+        let retry_config = &self.retry_config;
+        if self.attempts >= retry_config.max_attempts || !retry_config.retry_on.contains(&condition) || !self.body.is_replayable() {
+            return None;
+        }
+        if !retry_config.retry_non_idempotent && !request_utils::is_idempotent_method(&self.parts.method) {
+            return None;
+        }
+        let exponent = self.attempts.saturating_sub(1).min(u32::BITS as usize - 1) as u32;
+        let backoff = retry_config.base_delay.saturating_mul(2u64.saturating_pow(exponent)).min(retry_config.max_delay);
+        let retry_delay = Duration::from_millis(rand::thread_rng().gen_range(0..=backoff));
+        match self.deadline {
+            Some(deadline) if tokio::time::Instant::now() + retry_delay >= deadline => None,
+            _ => Some(retry_delay)
+        }
+    }
+}
+
+///forwards an attempt of the request to the upstream base url, streaming the request and response bodies
+/// 
+/// failed connections and 502, 503 and 504 responses fail the attempt so the caller can retry it,
+/// waiting on containers that are still starting is done by the readiness gate.
+/// on_close receives the time the destination took to respond and runs once the response body is finished
+pub async fn forward_request<F, Fut>(pending_request:&mut PendingRequest, upstream:String, route:&Route, on_close:F) -> ForwardAttempt
+where
+    F: FnOnce(Duration) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static
 {
-    
-    let (parts, body) = request.into_parts();
-    let time = std::time::SystemTime::now();
-    let current_time = time.duration_since(UNIX_EPOCH).unwrap().as_secs();
-    let maximum_time_attempt_in_seconds:u64 = env::var("MAX_TIME_RETRY").unwrap().parse::<u64>().unwrap();
-    
-    let client_builder = reqwest::ClientBuilder::new();
-    let client = client_builder.use_rustls_tls().danger_accept_invalid_certs(true).build().unwrap();
-    let bytes = to_bytes(body, usize::MAX).await.unwrap();
+    let mut close_guard = pooled_close_guard(&upstream, on_close).await;
+    pending_request.attempts += 1;
+    let (client, base_url) = match upstream_clients().backend_client(route.backend.as_ref(), &upstream, false).await {
+        Ok(backend_client) => backend_client,
+        Err(err_string) => {
+            println!("[ERROR] {}", err_string);
+            return ForwardAttempt::Done(proxy_error((StatusCode::BAD_GATEWAY, "[ERROR] Cannot create a connection to the destination").into_response()))
+        }
+    };
 
-    let headers = parts.headers.clone();
-    //let uri = extract_uri(&parts.uri, prefix);
-    let uri = extract_uri(&parts.uri);
+    let parts = &pending_request.parts;
+    let uri = rewrite_uri(&parts.uri, route, rewrite_pattern(&parts.extensions), parts.extensions.get::<RouteParams>());
     
-	let url =  format!("https://localhost:{}{}",public_port.unwrap(),uri);
-	loop { //try to connect till it becomes OK
-		let attempt_time = std::time::SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-		if attempt_time - current_time < maximum_time_attempt_in_seconds {
-			println!("[PROCESS] current attempt time: {:#?}/{} to : {}", (attempt_time - current_time), maximum_time_attempt_in_seconds, &url);
-			let request_result = client.request(parts.method.clone(), &url).headers(headers.clone()).body(bytes.clone()).send().await;
-			let _mr_ok_res = match request_result {
-				Ok(result) => {
-					let status = result.status();
-					//let bytes = result.bytes().await.unwrap();
-					let headers = result.headers().clone();
-					let body = Body::try_from(result.bytes().await.unwrap()).unwrap();
-					
-					let status_code = StatusCode::from_u16(status.as_u16()).unwrap();
-					println!("[PROCESS] Responded");
-						//todo insert to request db
-					return (status_code,headers,body).into_response();
-					
-				}
-				Err(error) => { //i think this is wrong
-					println!("[PROCESS] Failed... Retrying");
-				}
-			};
-		}else{
-			println!("[PROCESS] Request attempt exceeded AttemptTimeThreshold={}s", &maximum_time_attempt_in_seconds);
-			return (StatusCode::REQUEST_TIMEOUT).into_response()
-		}  
+	let url =  format!("{}{}", base_url, uri);
+	let body = pending_request.body.take().unwrap_or_default();
+	println!("[PROCESS] Forwarding request to : {}", &url);
+	let request_future = client.request(parts.method.clone(), &url).headers(parts.headers.clone()).body(body).send();
+	let request_result = match pending_request.deadline {
+		Some(deadline) => match tokio::time::timeout_at(deadline, request_future).await {
+			Ok(request_result) => request_result,
+			Err(_) => {
+				println!("[ERROR] {} did not respond within {}s", &url, pending_request.timeout_config.request_timeout.unwrap_or_default());
+				return ForwardAttempt::Done((StatusCode::GATEWAY_TIMEOUT, "[ERROR] The destination did not respond in time").into_response())
+			}
+		},
+		None => request_future.await
+	};
+	match request_result {
+		Ok(result) => {
+			close_guard.responded();
+			let status_code = StatusCode::from_u16(result.status().as_u16()).unwrap();
+			let mut headers = result.headers().clone();
+			request_utils::remove_hop_by_hop_headers(&mut headers);
+			println!("[PROCESS] Responded");
+				//todo insert to request db
+			//streamed responses such as event streams stay open past the request timeout and are only bound by the idle timeout
+			let body_deadline = if is_streaming_response(&headers, &pending_request.timeout_config) { None } else { pending_request.deadline };
+			let idle_timeout = pending_request.timeout_config.idle_timeout.map(Duration::from_secs);
+			let response = (status_code, headers, response_body(result, idle_timeout, body_deadline, close_guard)).into_response();
+			match status_code {
+				StatusCode::BAD_GATEWAY => ForwardAttempt::Failed(RetryCondition::BadGateway, response),
+				StatusCode::SERVICE_UNAVAILABLE => ForwardAttempt::Failed(RetryCondition::ServiceUnavailable, response),
+				StatusCode::GATEWAY_TIMEOUT => ForwardAttempt::Failed(RetryCondition::GatewayTimeout, response),
+				_ => ForwardAttempt::Done(response)
+			}
+		}
+		Err(error) if is_length_limit_error(&error) => {
+			ForwardAttempt::Done(proxy_error(payload_too_large(pending_request.body_config.max_size.unwrap_or_default())))
+		}
+		Err(error) => {
+			println!("[ERROR] Failed to forward request to {}: {}", &url, error);
+			let response = (StatusCode::BAD_GATEWAY, "[ERROR] Cannot create a connection to the destination").into_response();
+			if error.is_connect() {
+				upstream_clients().record_connect_error(&upstream).await;
+				//nothing was sent to the destination so the request can be retried
+				ForwardAttempt::Failed(RetryCondition::ConnectError, response)
+			}else{
+				ForwardAttempt::Done(response)
+			}
+		}
 	}
-    
-    
 }
 
 pub fn extract_uri (uri:&Uri)->String {
@@ -283,3 +738,48 @@ pub fn extract_uri (uri:&Uri)->String {
     };
     new_uri
 }
+
+///returns the rewrite pattern of the route the request matched, compiled when the route table was built
+fn rewrite_pattern(extensions:&Extensions) -> Option<&Regex> {
+    extensions.get::<Arc<RouteEntry>>().and_then(|entry| entry.rewrite_pattern.as_ref())
+}
+
+///returns the path and query the request is forwarded with after the rewrite of its route
+/// 
+/// the prefix of replace rewrites and the replacement of regex rewrites can refer to the parameters of the address as {name}
+pub fn rewrite_uri(uri:&Uri, route:&Route, rewrite_pattern:Option<&Regex>, params:Option<&RouteParams>) -> String {
+    let rewrite = match &route.rewrite {
+        Some(rewrite) => rewrite,
+        None => return extract_uri(uri)
+    };
+    let expand = |template:&str| params.map_or_else(|| template.to_string(), |params| params.expand(template));
+    let path = uri.path();
+    let rewritten_path = match rewrite.mode {
+        PathRewriteMode::Strip | PathRewriteMode::Replace => {
+            let matched_path = params.map_or(route.address.as_str(), |params| params.matched_path.as_str()).trim_end_matches('/');
+            let remainder = path.strip_prefix(matched_path).unwrap_or(path);
+            let prefix = match rewrite.mode {
+                PathRewriteMode::Replace => expand(route.prefix.as_deref().unwrap_or_default()),
+                _ => String::new()
+            };
+            let prefix = prefix.trim_end_matches('/');
+            if remainder.starts_with('/') {
+                format!("{}{}", prefix, remainder)
+            }else{
+                format!("{}/{}", prefix, remainder)
+            }
+        },
+        PathRewriteMode::Regex => match rewrite_pattern {
+            Some(pattern) => pattern.replace(path, expand(rewrite.replacement.as_deref().unwrap_or_default())).into_owned(),
+            None => {
+                println!("[ERROR] Route {} has an invalid rewrite pattern", &route.address);
+                path.to_string()
+            }
+        }
+    };
+    let rewritten_path = if rewritten_path.starts_with('/') { rewritten_path } else { format!("/{}", rewritten_path) };
+    match uri.query() {
+        Some(query) => format!("{}?{}", rewritten_path, query),
+        None => rewritten_path
+    }
+}
@@ -1,2 +1,13 @@
+pub mod circuit_breaker_utils;
 pub mod docker_utils;
-pub mod mongodb_utils;
\ No newline at end of file
+pub mod health_utils;
+pub mod load_balancer_utils;
+pub mod mongodb_utils;
+pub mod port_utils;
+pub mod readiness_utils;
+pub mod request_utils;
+pub mod route_table_utils;
+pub mod scaling_utils;
+pub mod tls_utils;
+pub mod traffic_split_utils;
+pub mod upstream_utils;
\ No newline at end of file
@@ -0,0 +1,210 @@
+use std::{collections::VecDeque, time::{Duration, Instant}};
+
+use serde::Serialize;
+
+use crate::models::docker_models::CircuitBreakerConfig;
+
+///state of a circuit breaker, open circuits fail requests fast and half-open circuits only let probes through
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    #[default]
+    Closed,
+    Open,
+    HalfOpen
+}
+
+///failure rate circuit breaker of a container
+#[derive(Debug, Default)]
+pub struct CircuitBreaker {
+    state: CircuitState,
+    outcomes: VecDeque<bool>, //true for failed requests, holds the last window requests while closed
+    opened_at: Option<Instant>,
+    probes_in_flight: usize,
+    probe_successes: usize,
+    last_probe_at: Option<Instant>
+}
+
+impl CircuitBreaker {
+    pub fn state(&self) -> CircuitState {
+        self.state
+    }
+
+    ///returns true when the breaker lets a request through
+    ///
+    /// probes that never reported back are given up on after open_duration so a half-open circuit cannot get stuck
+    pub fn allows_request(&self, config:&CircuitBreakerConfig) -> bool {
+        let open_duration = Duration::from_secs(config.open_duration);
+        match self.state {
+            CircuitState::Closed => true,
+            CircuitState::Open => self.opened_at.is_none_or(|opened_at| opened_at.elapsed() >= open_duration),
+            CircuitState::HalfOpen => self.probes_in_flight < config.half_open_probes || self.last_probe_at.is_none_or(|last_probe_at| last_probe_at.elapsed() >= open_duration)
+        }
+    }
+
+    ///lets a request through, returns the new state when an open circuit moved to half-open and the request became a probe
+    pub fn acquire(&mut self, config:&CircuitBreakerConfig) -> Option<CircuitState> {
+        match self.state {
+            CircuitState::Closed => None,
+            CircuitState::Open => {
+                self.state = CircuitState::HalfOpen;
+                self.probes_in_flight = 1;
+                self.probe_successes = 0;
+                self.last_probe_at = Some(Instant::now());
+                Some(CircuitState::HalfOpen)
+            },
+            CircuitState::HalfOpen => {
+                if self.last_probe_at.is_none_or(|last_probe_at| last_probe_at.elapsed() >= Duration::from_secs(config.open_duration)) {
+                    self.probes_in_flight = 0;
+                }
+                self.probes_in_flight += 1;
+                self.last_probe_at = Some(Instant::now());
+                None
+            }
+        }
+    }
+
+    ///gives back a probe that was let through without an outcome, e.g. when the proxy failed the request itself
+    pub fn release(&mut self) {
+        if self.state == CircuitState::HalfOpen {
+            self.probes_in_flight = self.probes_in_flight.saturating_sub(1);
+        }
+    }
+
+    ///records the outcome of a request, returns the new state when the circuit opened or closed
+    ///
+    /// outcomes of requests sent before the circuit opened are ignored
+    pub fn record(&mut self, is_success:bool, config:&CircuitBreakerConfig) -> Option<CircuitState> {
+        match self.state {
+            CircuitState::Closed => {
+                self.outcomes.push_back(!is_success);
+                while self.outcomes.len() > config.window.max(1) {
+                    self.outcomes.pop_front();
+                }
+                let failures = self.outcomes.iter().filter(|is_failure| **is_failure).count();
+                if self.outcomes.len() >= config.minimum_requests.max(1) && failures as f64 >= config.failure_rate * self.outcomes.len() as f64 {
+                    return Some(self.open());
+                }
+                None
+            },
+            CircuitState::HalfOpen if !is_success => Some(self.open()),
+            CircuitState::HalfOpen => {
+                self.probes_in_flight = self.probes_in_flight.saturating_sub(1);
+                self.probe_successes += 1;
+                if self.probe_successes >= config.half_open_probes {
+                    self.state = CircuitState::Closed;
+                    self.outcomes.clear();
+                    return Some(CircuitState::Closed);
+                }
+                None
+            },
+            CircuitState::Open => None
+        }
+    }
+
+    fn open(&mut self) -> CircuitState {
+        self.state = CircuitState::Open;
+        self.opened_at = Some(Instant::now());
+        self.outcomes.clear();
+        self.probes_in_flight = 0;
+        self.probe_successes = 0;
+        CircuitState::Open
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker_config(open_duration:u64) -> CircuitBreakerConfig {
+        CircuitBreakerConfig { window: 4, minimum_requests: 4, failure_rate: 0.5, open_duration, half_open_probes: 2 }
+    }
+
+    fn opened_breaker(config:&CircuitBreakerConfig) -> CircuitBreaker {
+        let mut circuit_breaker = CircuitBreaker::default();
+        for is_success in [true, true, false] {
+            circuit_breaker.record(is_success, config);
+        }
+        assert_eq!(circuit_breaker.record(false, config), Some(CircuitState::Open));
+        circuit_breaker
+    }
+
+    #[test]
+    fn closed_circuit_opens_at_failure_threshold() {
+        let config = breaker_config(60);
+        let mut circuit_breaker = CircuitBreaker::default();
+        //failures below minimum_requests do not open the circuit
+        for _ in 0..3 {
+            assert_eq!(circuit_breaker.record(false, &config), None);
+        }
+        assert_eq!(circuit_breaker.record(false, &config), Some(CircuitState::Open));
+
+        let mut circuit_breaker = CircuitBreaker::default();
+        for is_success in [true, true, true, false] {
+            assert_eq!(circuit_breaker.record(is_success, &config), None);
+        }
+        assert_eq!(circuit_breaker.state(), CircuitState::Closed);
+        //the window slides, two failures out of the last four requests reach the failure rate
+        assert_eq!(circuit_breaker.record(false, &config), Some(CircuitState::Open));
+    }
+
+    #[test]
+    fn open_circuit_rejects_until_cooldown() {
+        let config = breaker_config(60);
+        let circuit_breaker = opened_breaker(&config);
+        assert_eq!(circuit_breaker.state(), CircuitState::Open);
+        assert!(!circuit_breaker.allows_request(&config));
+    }
+
+    #[test]
+    fn open_circuit_turns_half_open_after_cooldown() {
+        let config = breaker_config(0);
+        let mut circuit_breaker = opened_breaker(&config);
+        assert!(circuit_breaker.allows_request(&config));
+        assert_eq!(circuit_breaker.acquire(&config), Some(CircuitState::HalfOpen));
+        assert_eq!(circuit_breaker.state(), CircuitState::HalfOpen);
+    }
+
+    #[test]
+    fn half_open_circuit_closes_after_successful_probes() {
+        let config = breaker_config(0);
+        let mut circuit_breaker = opened_breaker(&config);
+        circuit_breaker.acquire(&config);
+        circuit_breaker.acquire(&config);
+        assert_eq!(circuit_breaker.record(true, &config), None);
+        assert_eq!(circuit_breaker.record(true, &config), Some(CircuitState::Closed));
+        assert!(circuit_breaker.allows_request(&config));
+    }
+
+    #[test]
+    fn half_open_circuit_reopens_on_failed_probe() {
+        let config = breaker_config(60);
+        let mut circuit_breaker = opened_breaker(&breaker_config(0));
+        circuit_breaker.acquire(&config);
+        assert_eq!(circuit_breaker.state(), CircuitState::HalfOpen);
+        assert_eq!(circuit_breaker.record(false, &config), Some(CircuitState::Open));
+        assert!(!circuit_breaker.allows_request(&config));
+    }
+
+    #[test]
+    fn half_open_circuit_limits_probes() {
+        let config = breaker_config(60);
+        let mut circuit_breaker = opened_breaker(&breaker_config(0));
+        circuit_breaker.acquire(&config);
+        assert!(circuit_breaker.allows_request(&config));
+        circuit_breaker.acquire(&config);
+        assert!(!circuit_breaker.allows_request(&config));
+    }
+
+    #[test]
+    fn released_probe_lets_another_probe_through() {
+        let config = breaker_config(60);
+        let mut circuit_breaker = opened_breaker(&breaker_config(0));
+        circuit_breaker.acquire(&config);
+        circuit_breaker.acquire(&config);
+        assert!(!circuit_breaker.allows_request(&config));
+        circuit_breaker.release();
+        assert!(circuit_breaker.allows_request(&config));
+        assert_eq!(circuit_breaker.state(), CircuitState::HalfOpen);
+    }
+}
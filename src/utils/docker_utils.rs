@@ -1,48 +1,39 @@
-use std::{collections::HashMap, process::exit, str::FromStr, sync::OnceLock, time::{Duration, UNIX_EPOCH} };
+use std::{collections::HashMap, str::FromStr, sync::OnceLock, time::UNIX_EPOCH };
 use axum::response::IntoResponse;
-use bollard::{container::{self, Config, CreateContainerOptions, ListContainersOptions, StartContainerOptions}, image::ListImagesOptions, secret::{ContainerStateStatusEnum, HostConfig, ImageSummary, PortBinding}, Docker};
+use bollard::{container::{Config, CreateContainerOptions, ListContainersOptions, RemoveContainerOptions, RestartContainerOptions, StartContainerOptions, StopContainerOptions}, image::ListImagesOptions, network::{CreateNetworkOptions, InspectNetworkOptions}, secret::{ContainerStateStatusEnum, HostConfig, PortBinding, RestartPolicy, RestartPolicyNameEnum}, Docker};
 use hyper::StatusCode;
-use mongodb::bson::{doc, oid::ObjectId};
-use rand::Rng;
+use mongodb::{bson::{doc, oid::ObjectId}, options::UpdateOptions};
 
-use crate::models::{docker_models::{self, ContainerInsert, Image, ImageInsert, LoadBalancer, LoadBalancerInsert, Route}, load_balancer_models::{ self, ActiveServiceDirectory, LOAD_BALANCERS}};
+use crate::models::{docker_models::{self, BackendScheme, ContainerInsert, ContainerTemplate, Image, ImageInsert, LoadBalancer, LoadBalancerInsert, RestartPolicyName, Route}, load_balancer_models::{ self, ActiveServiceDirectory, LOAD_BALANCERS}};
 
-use super::mongodb_utils::DBCollection;
+use super::{circuit_breaker_utils::CircuitBreaker, health_utils::ContainerHealth, load_balancer_utils::LoadBalancerBehavior, mongodb_utils::DBCollection, port_utils, upstream_utils::upstream_clients};
 //balancer per image
 pub static DOCKER_CONNECTION:OnceLock<Docker> = OnceLock::new();
 
-pub enum LoadBalancerBehavior {
-    RoundRobin
-}
-
-impl ToString for LoadBalancerBehavior {
-    fn to_string(&self) -> String {
-        match &self {
-            &Self::RoundRobin => "round_robin".to_string()
-        }
-    }
-}
-
 /// returns index of load balancer
-pub async fn get_load_balancer_instances(mongo_image_id:ObjectId, container_address:String) -> String{
+/// 
+/// load_balancer_key is the key of the route or of one of its variants, each image gets a load balancer of its own
+pub async fn get_load_balancer_instances(mongo_image_id:ObjectId, route:&Route, load_balancer_key:String) -> String{
+    let container_address = load_balancer_key;
     
     //check local records
     match ActiveServiceDirectory::get_load_balancer_key(container_address.clone()).await {
         Some(index)=>{
-            return index;
+            index
         },
         None =>{
             //perform a database lookup for a load_balancer
-            let load_balancer_result = DBCollection::LOADBALANCERS.collection::<LoadBalancer>().await.find_one(doc!{"mongo_image_reference" : mongo_image_id.clone()}, None).await.unwrap();
+            let load_balancer_result = DBCollection::LOADBALANCERS.collection::<LoadBalancer>().await.find_one(doc!{"mongo_image_reference" : mongo_image_id}, None).await.unwrap();
             match load_balancer_result {
                 Some(load_balancer) => {
                     //there is an instance of the load balancer and shape of past instance
-                    let index = ActiveServiceDirectory::create_load_balancer(load_balancer._id.to_hex(), container_address, LoadBalancerBehavior::RoundRobin, load_balancer.containers, Some(false)).await;
-                    return index;
+                    let behavior = LoadBalancerBehavior::from_behavior(&load_balancer.behavior).unwrap_or(LoadBalancerBehavior::RoundRobin);
+                    let index = ActiveServiceDirectory::create_load_balancer(load_balancer._id.to_hex(), container_address, route, behavior, load_balancer.containers).await;
+                    index
                 },
                 None => {
                     
-                    return  create_load_balancer_instance(mongo_image_id, container_address).await
+                    return  create_load_balancer_instance(mongo_image_id, container_address, route).await
                 }
             }
         }
@@ -53,132 +44,300 @@ pub async fn get_load_balancer_instances(mongo_image_id:ObjectId, container_addr
     
 }
 ///returns load_balancer_key : [type String]
-pub async fn create_load_balancer_instance(mongo_image_id:ObjectId, container_address:String) -> String{
+pub async fn create_load_balancer_instance(mongo_image_id:ObjectId, load_balancer_key:String, route:&Route) -> String{
    
     let doc: LoadBalancerInsert = LoadBalancerInsert{
-        mongo_image_reference: mongo_image_id.clone(),
+        mongo_image_reference: mongo_image_id,
         head: 0,
         behavior: LoadBalancerBehavior::RoundRobin.to_string(),
         containers: vec![],
     };
     let create_result: mongodb::results::InsertOneResult = DBCollection::LOADBALANCERS.collection::<LoadBalancerInsert>().await.insert_one(doc, None).await.unwrap();
-    ActiveServiceDirectory::create_load_balancer(create_result.inserted_id.as_object_id().unwrap().to_hex(), container_address, LoadBalancerBehavior::RoundRobin, vec![], Some(false)).await
+    ActiveServiceDirectory::create_load_balancer(create_result.inserted_id.as_object_id().unwrap().to_hex(), load_balancer_key, route, LoadBalancerBehavior::RoundRobin, vec![]).await
     
 }
+///persists the behavior on the load balancer of the image
+/// 
+/// creates the load balancer record when the image does not have one yet
+pub async fn set_load_balancer_behavior(mongo_image_id:&ObjectId, behavior:LoadBalancerBehavior) -> Result<(), mongodb::error::Error>{
+    let update_options = UpdateOptions::builder().upsert(true).build();
+    DBCollection::LOADBALANCERS.collection::<LoadBalancer>().await.update_one(doc!{
+        "mongo_image_reference": mongo_image_id
+    }, doc!{
+        "$set": { "behavior": behavior.to_string() },
+        "$setOnInsert": { "head": 0_i64, "containers": Vec::<String>::new() }
+    }, update_options).await?;
+    Ok(())
+}
 /// updates the load_balancer of the new container created
 /// 
-/// returns [type Option]<([type String],[type usize])> as (docker_container_id, container_public_port)
-pub async fn create_container_instance (mongo_image:&ObjectId, load_balancer_id:&String, mut current_containers:Vec<String>)
-    -> Option<load_balancer_models::Container>{
+/// the host port is reserved through [fn port_utils::reserve_port], errors when no port is left or the container cannot be created.
+/// containers are attached to DOCKER_NETWORK without a host port when it is set and use the exposed port and container template of route,
+/// which also applies to the images of its variants
+pub async fn create_container_instance (route:&Route, mongo_image:&ObjectId, load_balancer_id:&str, mut current_containers:Vec<String>)
+    -> Result<load_balancer_models::Container, String>{
+    let load_balancer_o_id = ObjectId::from_str(load_balancer_id).map_err(|_| format!("Load balancer id {} is invalid", load_balancer_id))?;
     println!("[PROCESS] Fetching image {:#?}", mongo_image);
-    let docker_image = DBCollection::IMAGES.collection::<Image>().await.find_one(doc!{
+    let docker_image = match DBCollection::IMAGES.collection::<Image>().await.find_one(doc!{
         "_id": mongo_image
-    }, None).await.unwrap().unwrap().docker_image_id;
+    }, None).await {
+        Ok(Some(image)) => image.docker_image_id,
+        Ok(None) => return Err(format!("Image [{}] is not registered", mongo_image)),
+        Err(error) => return Err(format!("Cannot fetch image {}: {}", mongo_image, error))
+    };
     let docker_image_exist = check_if_docker_image_exist(&docker_image).await;
     println!("[PROCESS] Creating container instance with image{}",&docker_image);
     
     if  docker_image_exist{
         
-        let route_find_result = DBCollection::ROUTES.collection::<Route>().await.find_one(doc! {"mongo_image" : &mongo_image}, None).await.unwrap().unwrap();
-        let container_port = route_find_result.exposed_port;
-
-        let starting_port = std::env::var("STARTING_PORT").unwrap().parse::<usize>().unwrap();
+        let container_port = &route.exposed_port;
 
-        let ending_port = std::env::var("ENDING_PORT").unwrap().parse::<usize>().unwrap();
-        
-        let local_port = rand::thread_rng().gen_range(starting_port..ending_port);
+        let docker_network = docker_network();
+        //network containers are reached on the exposed_port directly, so public_port holds the exposed_port
+        let (local_port, host_config) = match &docker_network {
+            Some(docker_network) => {
+                let exposed_port = container_port.parse::<usize>().map_err(|_| format!("Exposed port {} is invalid", container_port))?;
+                (exposed_port, HostConfig {
+                    network_mode: Some(docker_network.clone()),
+                    ..Default::default()
+                })
+            },
+            None => {
+                let local_port = port_utils::reserve_port().await?;
+                let mut port_binding = HashMap::new();
+                port_binding.insert(format!("{}/tcp",container_port), Some(vec![PortBinding{
+                    host_port: Some(local_port.clone().to_string()),
+                    host_ip: Some("0.0.0.0".to_string())
+                }]));
+                (local_port, HostConfig {
+                    port_bindings : Some(port_binding),
+                    ..Default::default()
+                })
+            }
+        };
+        let reserved_port = docker_network.is_none().then_some(local_port);
 
         let docker = DOCKER_CONNECTION.get().unwrap();
-        let mut port_binding = HashMap::new();
-        port_binding.insert(format!("{}/tcp",container_port), Some(vec![PortBinding{
-            host_port: Some(local_port.clone().to_string()),
-            host_ip: Some("0.0.0.0".to_string())
-        }]));
         let options = Some(CreateContainerOptions::<String>{..Default::default() });
-        let host_config:HostConfig = HostConfig {
-            port_bindings : Some(port_binding),
-            ..Default::default()
-        };
-        let config = Config {
+        let config = apply_container_template(Config {
             image: Some(docker_image.clone()),
             host_config: Some(host_config),
             ..Default::default()
+        }, route.container.as_ref());
+        let image_result = match DBCollection::IMAGES.collection::<Image>().await.find_one(doc!{
+            "docker_image_id": &docker_image
+        }, None).await {
+            Ok(image_result) => image_result,
+            Err(error) => {
+                if let Some(reserved_port) = reserved_port {
+                    port_utils::release_port(reserved_port).await;
+                }
+                return Err(format!("Cannot fetch image {}: {}", docker_image, error));
+            }
         };
-        let image_result = DBCollection::IMAGES.collection::<Image>().await.find_one(doc!{
-            "docker_image_id": docker_image
-        }, None).await.unwrap();
         match image_result {
             Some(image)=>{
-                let create_container_result = docker.create_container( options, config).await.unwrap();
+                let create_container_result = match docker.create_container( options, config).await {
+                    Ok(create_container_result) => create_container_result,
+                    Err(error) => {
+                        if let Some(reserved_port) = reserved_port {
+                            port_utils::release_port(reserved_port).await;
+                        }
+                        return Err(format!("Cannot create a container of image {} on port {}: {}", mongo_image, local_port, error));
+                    }
+                };
+                if let Some(reserved_port) = reserved_port {
+                    port_utils::bind_port(reserved_port, &create_container_result.id).await;
+                }
                 let doc = ContainerInsert { 
-                    mongo_image_reference: image._id.clone(), 
+                    mongo_image_reference: image._id, 
                     container_id: create_container_result.id.clone(), 
-                    public_port: local_port.clone()
+                    public_port: local_port,
+                    network: docker_network
+                };
+                let container_insert_result = DBCollection::CONTAINERS.collection::<ContainerInsert>().await.insert_one(doc, None).await
+                    .map_err(|error| error.to_string())
+                    .and_then(|insert_result| insert_result.inserted_id.as_object_id().ok_or("the inserted id is not an object id".to_string()));
+                let container_o_id = match container_insert_result {
+                    Ok(container_o_id) => container_o_id,
+                    Err(err_string) => {
+                        discard_created_container(&create_container_result.id, reserved_port).await;
+                        return Err(format!("Cannot store container {}: {}", &create_container_result.id, err_string));
+                    }
                 };
                 current_containers.push(create_container_result.id.clone());
-                let _load_balancer_update_result = DBCollection::LOADBALANCERS.collection::<docker_models::LoadBalancer>().await.find_one_and_update(
-                    doc!{"_id": ObjectId::from_str(&load_balancer_id.as_str()).unwrap()},
+                let load_balancer_update_result = match DBCollection::LOADBALANCERS.collection::<docker_models::LoadBalancer>().await.find_one_and_update(
+                    doc!{"_id": load_balancer_o_id},
                     doc!{
                         "$set": {"containers": current_containers}
-                }, None).await;
-                let container_insert_result = DBCollection::CONTAINERS.collection::<ContainerInsert>().await.insert_one(doc, None).await.unwrap();
+                }, None).await {
+                    Ok(Some(_)) => Ok(()),
+                    Ok(None) => Err(format!("Load balancer {} no longer exists", load_balancer_id)),
+                    Err(error) => Err(format!("Cannot add container {} to load balancer {}: {}", &create_container_result.id, load_balancer_id, error))
+                };
+                if let Err(err_string) = load_balancer_update_result {
+                    if let Err(error) = DBCollection::CONTAINERS.collection::<ContainerInsert>().await.delete_one(doc!{"_id": container_o_id}, None).await {
+                        println!("[ERROR] Cannot remove container record {}: {}", container_o_id, error);
+                    }
+                    discard_created_container(&create_container_result.id, reserved_port).await;
+                    return Err(err_string);
+                }
                 
                 let container = load_balancer_models::Container{
-                    id: container_insert_result.inserted_id.as_object_id().unwrap().to_hex(),
+                    id: container_o_id.to_hex(),
                     container_id: create_container_result.id.clone(),
                     public_port: local_port,
+                    network_ip: None,
                     last_accepted_request: None,
                     last_replied_request: None,
+                    in_flight: 0,
+                    weight: 1,
+                    latency_ewma: None,
+                    health: ContainerHealth::Healthy,
+                    consecutive_successes: 0,
+                    consecutive_failures: 0,
+                    circuit_breaker: CircuitBreaker::default(),
                 };
                 println!("[PROCESS] Created_container model");
               
-                return Some(container);
+                Ok(container)
             },
-            None=>{None}
+            None=>{
+                if let Some(reserved_port) = reserved_port {
+                    port_utils::release_port(reserved_port).await;
+                }
+                Err(format!("Image [{}] is not registered", mongo_image))
+            }
         }
         
     }else{
         println!("[PROCESS] Image [{}] does not exist",&docker_image);
-        return None
+        Err(format!("Image [{}] does not exist", &docker_image))
     }
     
 }
 
-pub async fn create_container_instance_by_load_balancer_key(load_balancer_key:&String)->Option<load_balancer_models::Container>{
+///removes a created docker container whose records could not be stored and releases its reserved port
+async fn discard_created_container(docker_container_id:&String, reserved_port:Option<usize>){
+    remove_docker_container(docker_container_id).await;
+    if let Some(reserved_port) = reserved_port {
+        port_utils::release_port(reserved_port).await;
+    }
+}
+
+///sets the env, mounts, limits, command and restart policy of the container template on the container config
+fn apply_container_template(mut config:Config<String>, container_template:Option<&ContainerTemplate>) -> Config<String> {
+    let container_template = match container_template {
+        Some(container_template) => container_template,
+        None => return config
+    };
+    config.env = container_template.env.as_ref().map(|env| env.iter().map(|(name, value)| format!("{}={}", name, value)).collect());
+    config.volumes = container_template.volumes.as_ref().map(|volumes| volumes.iter().map(|volume| (volume.clone(), HashMap::new())).collect());
+    config.entrypoint = container_template.entrypoint.clone();
+    config.cmd = container_template.cmd.clone();
+    config.user = container_template.user.clone();
+    config.working_dir = container_template.working_dir.clone();
+    config.labels = container_template.labels.clone();
+    let mut host_config = config.host_config.take().unwrap_or_default();
+    host_config.binds = container_template.binds.clone();
+    host_config.memory = container_template.memory;
+    host_config.nano_cpus = container_template.cpus.map(|cpus| (cpus * 1_000_000_000.0) as i64);
+    host_config.restart_policy = container_template.restart_policy.as_ref().map(|restart_policy| RestartPolicy {
+        name: Some(match restart_policy.name {
+            RestartPolicyName::No => RestartPolicyNameEnum::NO,
+            RestartPolicyName::Always => RestartPolicyNameEnum::ALWAYS,
+            RestartPolicyName::UnlessStopped => RestartPolicyNameEnum::UNLESS_STOPPED,
+            RestartPolicyName::OnFailure => RestartPolicyNameEnum::ON_FAILURE
+        }),
+        maximum_retry_count: restart_policy.max_retries
+    });
+    config.host_config = Some(host_config);
+    config
+}
+
+pub async fn create_container_instance_by_load_balancer_key(load_balancer_key:&String)->Result<load_balancer_models::Container, String>{
     println!("[PROCESS] Creating LoadBalancer by key");
-    let load_balancer_mutex = LOAD_BALANCERS.get().unwrap().lock().await;
-    let load_balancer = load_balancer_mutex.get(load_balancer_key).unwrap();
-    let load_balancer_id = &load_balancer.id;
-    let mongo_load_balancer = DBCollection::LOADBALANCERS.collection::<docker_models::LoadBalancer>().await.find_one(doc!{"_id": ObjectId::from_str(&load_balancer_id.as_str()).unwrap()}, None).await.unwrap().unwrap();
+    //the global guard is only held to read the load balancer, docker and mongo are not awaited under it
+    let (load_balancer_id, route_id, load_balancer_containers) = {
+        let load_balancer_mutex = LOAD_BALANCERS.get().unwrap().lock().await;
+        let load_balancer = load_balancer_mutex.get(load_balancer_key).ok_or(format!("Load balancer {} no longer exists", load_balancer_key))?;
+        (load_balancer.id.clone(), load_balancer.route_id, load_balancer.containers.clone())
+    };
+    let load_balancer_id = &load_balancer_id;
+    //the route can be removed while the autoscaler or health recovery is creating a container
+    let route = match DBCollection::ROUTES.collection::<Route>().await.find_one(doc!{"_id": route_id}, None).await {
+        Ok(Some(route)) => route,
+        Ok(None) => return Err(format!("Route {} of load balancer {} no longer exists", route_id, load_balancer_key)),
+        Err(error) => return Err(format!("Cannot fetch route {}: {}", route_id, error))
+    };
+    let mongo_load_balancer = match DBCollection::LOADBALANCERS.collection::<docker_models::LoadBalancer>().await.find_one(doc!{"_id": ObjectId::from_str(load_balancer_id.as_str()).map_err(|_| format!("Load balancer id {} is invalid", load_balancer_id))?}, None).await {
+        Ok(Some(mongo_load_balancer)) => mongo_load_balancer,
+        Ok(None) => return Err(format!("Load balancer {} no longer exists", load_balancer_id)),
+        Err(error) => return Err(format!("Cannot fetch load balancer {}: {}", load_balancer_id, error))
+    };
     let current_containers = mongo_load_balancer.containers;
-    let create_container_result = create_container_instance(&mongo_load_balancer.mongo_image_reference, &load_balancer_id, current_containers).await.unwrap();
+    let create_container_result = create_container_instance(&route, &mongo_load_balancer.mongo_image_reference, load_balancer_id, current_containers).await?;
     
-    let mut load_balancer_containers_mutex = load_balancer.containers.lock().await;
-    load_balancer_containers_mutex.push(create_container_result.container_id.clone());
-    return Some(create_container_result);
+    load_balancer_containers.lock().await.push(create_container_result.container_id.clone());
+    ActiveServiceDirectory::create_container_instance(create_container_result.id.clone(), create_container_result.container_id.clone(), create_container_result.public_port, None, 1).await;
+    Ok(create_container_result)
 }
 
-pub async fn remove_container_instance (load_balancer_key:&String, docker_container_id:&String)->Vec<String>{
+///removes the container from the stored load balancer and returns the remaining containers
+/// 
+/// returns None when the load balancer does not exist, e.g. after its route was removed
+pub async fn remove_container_instance (load_balancer_key:&String, docker_container_id:&String)->Option<Vec<String>>{
     
-    let load_balancers_mutex = LOAD_BALANCERS.get().unwrap().lock().await;
-    let load_balancer = load_balancers_mutex.get(load_balancer_key).unwrap();
+    let load_balancers_mutex = match LOAD_BALANCERS.get() {
+        Some(load_balancers_mutex) => load_balancers_mutex.lock().await,
+        None => return None
+    };
+    let load_balancer = match load_balancers_mutex.get(load_balancer_key) {
+        Some(load_balancer) => load_balancer,
+        None => {
+            println!("[ERROR] Cannot remove container:{} of non-existing load-balancer: {}", docker_container_id, load_balancer_key);
+            return None
+        }
+    };
     let load_balancer_id = load_balancer.id.clone();
+    //the database round-trips would otherwise block every request routed meanwhile
+    drop(load_balancers_mutex);
     
-    let load_balancer_ref = DBCollection::LOADBALANCERS.collection::<docker_models::LoadBalancer>().await.find_one(doc!{
-    "_id" : ObjectId::from_str(&load_balancer_id.as_str()).unwrap()
-    }, None).await.unwrap().unwrap();
+    let load_balancer_object_id = match ObjectId::from_str(load_balancer_id.as_str()) {
+        Ok(load_balancer_object_id) => load_balancer_object_id,
+        Err(err) => {
+            println!("[ERROR] Invalid load-balancer id {}: {}", load_balancer_id, err);
+            return None
+        }
+    };
+    let load_balancer_ref = match DBCollection::LOADBALANCERS.collection::<docker_models::LoadBalancer>().await.find_one(doc!{
+    "_id" : load_balancer_object_id
+    }, None).await {
+        Ok(Some(load_balancer_ref)) => load_balancer_ref,
+        Ok(None) => {
+            println!("[ERROR] Cannot remove container:{} of non-stored load-balancer: {}", docker_container_id, load_balancer_id);
+            return None
+        },
+        Err(err) => {
+            println!("[ERROR] Cannot fetch load-balancer {}: {}", load_balancer_id, err);
+            return None
+        }
+    };
     let mut containers: Vec<String> = load_balancer_ref.containers;
     if let Some(index) = containers.iter().position(|i_container| i_container == docker_container_id){
         containers.remove(index);
     }
     println!("[PROCESS] Removing container:{} from the loadbalancer:{}",docker_container_id, load_balancer_id);
-    let _load_balancer_update = DBCollection::LOADBALANCERS.collection::<docker_models::LoadBalancer>().await.find_one_and_update(doc!{
-        "_id": ObjectId::from_str(&load_balancer_id.as_str()).unwrap()
+    if let Err(err) = DBCollection::LOADBALANCERS.collection::<docker_models::LoadBalancer>().await.find_one_and_update(doc!{
+        "_id": load_balancer_object_id
     }, doc!{
         "$set" : {
             "containers" : containers.clone()
         }
-    }, None).await.unwrap().unwrap();
+    }, None).await {
+        println!("[ERROR] Cannot update the containers of load-balancer {}: {}", load_balancer_id, err);
+        return None
+    }
     println!("[PROCESS] Removing container:{} from the collection", docker_container_id);
     let _container_update = DBCollection::CONTAINERS.collection::<docker_models::Container>().await.find_one_and_delete(doc!{
         "container_id": &docker_container_id
@@ -186,7 +345,73 @@ pub async fn remove_container_instance (load_balancer_key:&String, docker_contai
 
     println!("[PROCESS] Database update on load balancer container removal");
     
-    return containers;
+    Some(containers)
+}
+
+///force removes the docker container, stopping it first if it is running
+pub async fn remove_docker_container(docker_container_id:&String){
+    //the stats of its upstream would otherwise be kept forever and passed on to the next container on its port
+    if let Some(public_port) = ActiveServiceDirectory::get_container_public_port(docker_container_id).await {
+        let host = ActiveServiceDirectory::get_container_network_ip(docker_container_id).await.unwrap_or_else(|| "localhost".to_string());
+        upstream_clients().remove_upstream_stats(&format!("{}:{}", host, public_port)).await;
+    }
+    let docker = DOCKER_CONNECTION.get().unwrap();
+    let remove_options = Some(RemoveContainerOptions {
+        force: true,
+        ..Default::default()
+    });
+    match docker.remove_container(docker_container_id, remove_options).await {
+        Ok(_) => {
+            port_utils::release_container_ports(docker_container_id).await;
+            println!("[PROCESS] Removed docker container {}", docker_container_id)
+        },
+        Err(_) => println!("[ERROR] Cannot remove docker container {}", docker_container_id)
+    }
+}
+
+///stops the docker container, it is started again by [fn try_start_container] on the next request
+pub async fn stop_docker_container(docker_container_id:&String){
+    let docker = DOCKER_CONNECTION.get().unwrap();
+    match docker.stop_container(docker_container_id, None::<StopContainerOptions>).await {
+        Ok(_) => println!("[PROCESS] Stopped docker container {}", docker_container_id),
+        Err(_) => println!("[ERROR] Cannot stop docker container {}", docker_container_id)
+    }
+}
+
+///restarts the docker container in place, used to recover unhealthy containers
+pub async fn restart_docker_container(docker_container_id:&String) -> Result<(), String>{
+    let docker = DOCKER_CONNECTION.get().unwrap();
+    match docker.restart_container(docker_container_id, None::<RestartContainerOptions>).await {
+        Ok(_) => {
+            println!("[PROCESS] Restarted docker container {}", docker_container_id);
+            //docker can hand out another ip on restart
+            record_container_network_ip(docker_container_id).await?;
+            Ok(())
+        },
+        Err(_) => Err(format!("Cannot restart container {}", docker_container_id))
+    }
+}
+
+pub async fn is_container_running(docker_container_id:&str) -> bool{
+    let docker = DOCKER_CONNECTION.get().unwrap();
+    match docker.inspect_container(docker_container_id, None).await {
+        Ok(container_inspect) => container_inspect.state.and_then(|state| state.status) == Some(ContainerStateStatusEnum::RUNNING),
+        Err(_) => false
+    }
+}
+
+///removes the container from the load balancer record and the containers collection
+/// 
+/// used when the load balancer is not loaded in memory
+pub async fn remove_container_record(load_balancer_id:&ObjectId, docker_container_id:&String){
+    let _ = DBCollection::LOADBALANCERS.collection::<docker_models::LoadBalancer>().await.update_one(doc!{
+        "_id": load_balancer_id
+    }, doc!{
+        "$pull": { "containers": docker_container_id }
+    }, None).await;
+    let _ = DBCollection::CONTAINERS.collection::<docker_models::Container>().await.delete_one(doc!{
+        "container_id": docker_container_id
+    }, None).await;
 }
 
 ///regsiters the docker_image reference if it does not exist
@@ -199,7 +424,7 @@ pub async fn register_docker_image(docker_image:&String)->Result<ObjectId, impl
         }, None).await.unwrap() {
             
             Some(image) => {
-                return Ok(image._id);        
+                Ok(image._id)
             },
             None => {
                 //image does not exist so we must register it
@@ -207,12 +432,12 @@ pub async fn register_docker_image(docker_image:&String)->Result<ObjectId, impl
                     docker_image_id: docker_image.clone()   ,
                 };
                 let image_insert_result = DBCollection::IMAGES.collection::<docker_models::ImageInsert>().await.insert_one(doc_insert, None).await.unwrap();
-                    return Ok(image_insert_result.inserted_id.as_object_id().unwrap())
+                    Ok(image_insert_result.inserted_id.as_object_id().unwrap())
                 }
             }
     }
     else{
-        return Err((StatusCode::BAD_REQUEST, "[ERROR] docker_image_id provided is an invalid reference").into_response());
+        Err((StatusCode::BAD_REQUEST, "[ERROR] docker_image_id provided is an invalid reference").into_response())
     }
 }
 
@@ -224,32 +449,34 @@ pub async fn check_if_docker_image_exist(docker_image: &String) -> bool{
         ..Default::default()
     });
     let docker_images_result = docker.list_images(options).await.unwrap();
-    docker_images_result.iter().filter(|image_summary| image_summary.id.ends_with(docker_image)).collect::<Vec<&ImageSummary>>().len() > 0
+    docker_images_result.iter().any(|image_summary| image_summary.id.ends_with(docker_image))
 
 }
-///fetches the container id
-pub async fn route_container(load_balancer_string:String) 
--> (String, usize) 
+///fetches the container id, avoiding the excluded containers when another one is available
+pub async fn route_container(load_balancer_string:String, affinity_key:Option<String>, excluded_containers:&[String]) 
+-> Result<(String, usize), String> 
 {
-    ActiveServiceDirectory::validate_load_balancer_containers(load_balancer_string.clone()).await;
-    ActiveServiceDirectory::next_container(load_balancer_string.clone()).await
+    ActiveServiceDirectory::validate_load_balancer_containers(load_balancer_string.clone()).await?;
+    ActiveServiceDirectory::next_container(load_balancer_string.clone(), affinity_key, excluded_containers).await
     
 }
 
 ///docker_container_id is based on docker_container_instance and not from the mongodb_container_id
-pub async fn try_start_container(docker_container_id:&String)->Result<(),String>{
+/// 
+/// returns true when the container was started by this call and false when it was already running
+pub async fn try_start_container(docker_container_id:&String)->Result<bool,String>{
 
     let docker = DOCKER_CONNECTION.get().unwrap();
     //check if it is running
-    let container_summary_result = docker.inspect_container(&docker_container_id, None).await;
+    let container_summary_result = docker.inspect_container(docker_container_id, None).await;
     match container_summary_result{
         Ok(container_summary)=>{
             match container_summary.state.unwrap().status.unwrap() {
         
-                ContainerStateStatusEnum::RUNNING => {Ok(())},
+                ContainerStateStatusEnum::RUNNING => {Ok(false)},
                 ContainerStateStatusEnum::CREATED => {
                     let time:i64 = std::time::SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs().try_into().unwrap();
-                    let start_docker_result = docker.start_container(&docker_container_id, None::<StartContainerOptions<String>>).await;
+                    let start_docker_result = docker.start_container(docker_container_id, None::<StartContainerOptions<String>>).await;
                     match  start_docker_result{
                         Ok(_)=>{ 
                             let _container_update = DBCollection::CONTAINERS.collection::<docker_models::Container>().await.find_one_and_update(doc!{
@@ -262,15 +489,33 @@ pub async fn try_start_container(docker_container_id:&String)->Result<(),String>
                                     "time_responded": &time
                                 }
                             }, None).await.unwrap().unwrap();
-                            Ok(())
+                            if let Err(err_string) = record_container_network_ip(docker_container_id).await {
+                                println!("[ERROR] {}", err_string);
+                            }
+                            Ok(true)
                         },
                         Err(_) => {Err(format!("Cannot start container {}",docker_container_id))}
                     }
                 },
                 ContainerStateStatusEnum::EXITED => {
-                    let start_docker_result = docker.start_container(&docker_container_id, None::<StartContainerOptions<String>>).await;
+                    let time:i64 = std::time::SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs().try_into().unwrap();
+                    let start_docker_result = docker.start_container(docker_container_id, None::<StartContainerOptions<String>>).await;
                     match  start_docker_result{
-                        Ok(_)=>{ Ok(())},
+                        Ok(_)=>{
+                            //restarted containers count as fresh so the idle reaper does not stop them right away
+                            let _container_update = DBCollection::CONTAINERS.collection::<docker_models::Container>().await.find_one_and_update(doc!{
+                                "container_id": docker_container_id
+                            }, doc!{
+                                "$set" : {
+                                    "time_requested": &time,
+                                    "time_responded": &time
+                                }
+                            }, None).await;
+                            if let Err(err_string) = record_container_network_ip(docker_container_id).await {
+                                println!("[ERROR] {}", err_string);
+                            }
+                            Ok(true)
+                        },
                         Err(_) => {Err(format!("Cannot start container {}",docker_container_id))}
                     }
                 },
@@ -306,12 +551,84 @@ pub async fn verify_docker_containers(docker_containers:Vec<String>) -> Vec<Stri
 		},
 	};
 	
-    let new_container_list = result.iter().map(|container_summary| {
-        let container_id = <Option<String> as Clone>::clone(&container_summary.id).unwrap().to_string();
-        container_id
-    }).collect::<Vec<String>>();
+    let new_container_list = result.iter().map(|container_summary| container_summary.id.clone().unwrap()).collect::<Vec<String>>();
     ActiveServiceDirectory::create_container_instances(&new_container_list).await;
-    return new_container_list;
+    new_container_list
+}
+
+///returns the base url of a container published on the host
+pub fn container_upstream(public_port:usize, scheme:BackendScheme) -> String {
+    format!("{}://localhost:{}", scheme.url_scheme(), public_port)
+}
+
+///returns the user-defined docker network containers are attached to instead of publishing host ports
+pub fn docker_network() -> Option<String> {
+    std::env::var("DOCKER_NETWORK").ok().filter(|docker_network| !docker_network.is_empty())
+}
+
+///creates DOCKER_NETWORK as a bridge network when it does not exist yet
+pub async fn create_docker_network(docker_network:&str) -> Result<(), String>{
+    let docker = DOCKER_CONNECTION.get().unwrap();
+    if docker.inspect_network(docker_network, None::<InspectNetworkOptions<String>>).await.is_ok() {
+        return Ok(());
+    }
+    let create_network_options = CreateNetworkOptions {
+        name: docker_network,
+        driver: "bridge",
+        check_duplicate: true,
+        ..Default::default()
+    };
+    match docker.create_network(create_network_options).await {
+        Ok(_) => {
+            println!("[PROCESS] Created docker network {}", docker_network);
+            Ok(())
+        },
+        Err(error) => Err(format!("Cannot create docker network {}: {}", docker_network, error))
+    }
+}
+
+///returns the base url requests to a running container are forwarded to
+/// 
+/// containers attached to DOCKER_NETWORK are reached on the network ip recorded when they were started, public_port is then their exposed_port.
+/// other containers are reached on the port published on the host, both over the scheme of the route
+pub async fn resolve_container_upstream(docker_container_id:&String, public_port:usize, scheme:BackendScheme) -> Result<String, String>{
+    if docker_network().is_none() {
+        return Ok(container_upstream(public_port, scheme));
+    }
+    let network_ip = match ActiveServiceDirectory::get_container_network_ip(docker_container_id).await {
+        Some(network_ip) => Some(network_ip),
+        //containers already running when the orchestrator started have no recorded ip yet
+        None => record_container_network_ip(docker_container_id).await?
+    };
+    match network_ip {
+        Some(network_ip) => Ok(format!("{}://{}:{}", scheme.url_scheme(), network_ip, public_port)),
+        //containers created before the network was configured keep their host port
+        None => Ok(container_upstream(public_port, scheme))
+    }
+}
+
+///inspects the ip of the container on DOCKER_NETWORK and records it on the container, docker assigns it when the container starts
+pub async fn record_container_network_ip(docker_container_id:&String) -> Result<Option<String>, String>{
+    let docker_network = match docker_network() {
+        Some(docker_network) => docker_network,
+        None => return Ok(None)
+    };
+    let docker = DOCKER_CONNECTION.get().unwrap();
+    let container_inspect = docker.inspect_container(docker_container_id, None).await.map_err(|_| format!("cannot inspect container of id:{}", docker_container_id))?;
+    let network_ip = container_inspect.network_settings
+        .and_then(|network_settings| network_settings.networks)
+        .and_then(|mut networks| networks.remove(&docker_network))
+        .and_then(|endpoint| endpoint.ip_address)
+        .filter(|ip_address| !ip_address.is_empty());
+    if network_ip.is_some() {
+        ActiveServiceDirectory::set_container_network_ip(docker_container_id, network_ip.clone()).await;
+        let _container_update = DBCollection::CONTAINERS.collection::<docker_models::Container>().await.update_one(doc!{
+            "container_id": docker_container_id
+        }, doc!{
+            "$set": { "network_ip": &network_ip }
+        }, None).await;
+    }
+    Ok(network_ip)
 }
 
 pub async fn set_container_latest_request(docker_container_id:&String, latest_request:&String){
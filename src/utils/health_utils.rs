@@ -0,0 +1,140 @@
+use std::{collections::HashMap, sync::Arc, time::{Duration, Instant}};
+
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::models::{docker_models::HealthCheckConfig, load_balancer_models::{ActiveServiceDirectory, LOAD_BALANCERS}};
+
+use super::{docker_utils::{self, create_container_instance_by_load_balancer_key, resolve_container_upstream, try_start_container}, readiness_utils};
+
+const HEALTH_CHECK_TICK: Duration = Duration::from_secs(1);
+
+///health of a container as seen by the load balancer, unhealthy containers are ejected from selection
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContainerHealth {
+    Healthy,
+    Unhealthy
+}
+
+///background task that probes the containers of load balancers with an active health check
+///
+/// every load balancer is checked on the interval of its route,
+/// containers crossing the unhealthy threshold are recovered by [fn recover_container]
+pub async fn check_container_health(){
+    let mut interval = tokio::time::interval(HEALTH_CHECK_TICK);
+    loop {
+        interval.tick().await;
+        for (load_balancer_key, health_check) in due_load_balancers().await {
+            check_load_balancer_health(&load_balancer_key, &health_check).await;
+        }
+    }
+}
+
+///returns the validated load balancers whose health check interval has passed and marks them as checked
+async fn due_load_balancers() -> Vec<(String, HealthCheckConfig)> {
+    let load_balancers_mutex = LOAD_BALANCERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+    let mut due_load_balancers = Vec::new();
+    for (load_balancer_key, load_balancer) in load_balancers_mutex.iter() {
+        let health_check = match load_balancer.health_check.lock().await.clone() {
+            Some(health_check) if health_check.interval > 0 => health_check,
+            _ => continue
+        };
+        if !*load_balancer.validated.lock().await {
+            continue;
+        }
+        let mut last_health_check_at = load_balancer.last_health_check_at.lock().await;
+        if last_health_check_at.is_none_or(|last_health_check_at| last_health_check_at.elapsed() >= Duration::from_secs(health_check.interval)) {
+            *last_health_check_at = Some(Instant::now());
+            due_load_balancers.push((load_balancer_key.clone(), health_check));
+        }
+    }
+    due_load_balancers
+}
+
+async fn check_load_balancer_health(load_balancer_key:&String, health_check:&HealthCheckConfig){
+    //the load balancer can be torn down between ticks
+    let (docker_container_ids, backend) = match LOAD_BALANCERS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await.get(load_balancer_key) {
+        Some(load_balancer) => (load_balancer.containers.lock().await.clone(), load_balancer.backend.lock().await.clone()),
+        None => return
+    };
+    let scheme = backend.as_ref().map(|backend| backend.scheme).unwrap_or_default();
+    for docker_container_id in docker_container_ids {
+        //containers that are still starting are handled by their readiness gate
+        if readiness_utils::is_pending(&docker_container_id).await {
+            continue;
+        }
+        //stopped containers are probed again once a request starts them
+        let public_port = match ActiveServiceDirectory::get_container_public_port(&docker_container_id).await {
+            Some(public_port) if docker_utils::is_container_running(&docker_container_id).await => public_port,
+            _ => continue
+        };
+        let upstream = match resolve_container_upstream(&docker_container_id, public_port, scheme).await {
+            Ok(upstream) => upstream,
+            Err(_) => continue
+        };
+        let is_healthy = readiness_utils::probe(&upstream, health_check.path.as_deref(), backend.as_ref(), Duration::from_secs(health_check.timeout)).await;
+        match ActiveServiceDirectory::record_container_health(&docker_container_id, is_healthy, health_check.healthy_threshold, health_check.unhealthy_threshold).await {
+            Some(ContainerHealth::Unhealthy) => {
+                println!("[PROCESS] Container {} of {} failed {} health checks", &docker_container_id, load_balancer_key, health_check.unhealthy_threshold);
+                //recovering restarts or replaces the container, the other containers are still checked meanwhile
+                tokio::spawn(recover_container(docker_container_id, load_balancer_key.clone(), health_check.clone()));
+            },
+            Some(ContainerHealth::Healthy) => println!("[PROCESS] Container {} of {} is healthy again", &docker_container_id, load_balancer_key),
+            None => {}
+        }
+    }
+}
+
+///restarts an unhealthy container and replaces it when it cannot be restarted
+///
+/// without active checks the restarted container is trusted right away,
+/// otherwise it stays ejected until it passes healthy_threshold probes
+/// 
+/// errors when the load balancer was removed since the container was ejected or the container cannot be replaced
+pub async fn recover_container(docker_container_id:String, load_balancer_key:String, health_check:HealthCheckConfig)->Result<(), String>{
+    if ActiveServiceDirectory::get_load_balancer_containers(&load_balancer_key).await.is_none() {
+        println!("[PROCESS] Skipping recovery of container {}, load balancer {} no longer exists", &docker_container_id, &load_balancer_key);
+        return Err(format!("Load balancer {} no longer exists", &load_balancer_key));
+    }
+    println!("[PROCESS] Restarting unhealthy container {}", &docker_container_id);
+    match docker_utils::restart_docker_container(&docker_container_id).await {
+        Ok(_) => {
+            if health_check.interval == 0 {
+                ActiveServiceDirectory::set_container_health(&docker_container_id, ContainerHealth::Healthy).await;
+            }
+            Ok(())
+        },
+        Err(err_string) => {
+            println!("[ERROR] {}, replacing it", err_string);
+            ActiveServiceDirectory::retire_container(&docker_container_id, &load_balancer_key).await;
+            match create_container_instance_by_load_balancer_key(&load_balancer_key).await {
+                Ok(container) => {
+                    if let Err(err_string) = try_start_container(&container.container_id).await {
+                        println!("[ERROR] {}", err_string);
+                    }
+                    Ok(())
+                },
+                Err(err_string) => {
+                    println!("[ERROR] Failed to replace container {} of {}: {}", &docker_container_id, &load_balancer_key, err_string);
+                    Err(err_string)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn health_check() -> HealthCheckConfig {
+        HealthCheckConfig { path: None, interval: 10, timeout: 2, healthy_threshold: 2, unhealthy_threshold: 3, passive_failure_threshold: 5 }
+    }
+
+    #[tokio::test]
+    async fn recover_container_skips_load_balancer_removed_mid_check() {
+        let result = recover_container("container".to_string(), "removed-load-balancer".to_string(), health_check()).await;
+        assert!(result.is_err());
+    }
+}
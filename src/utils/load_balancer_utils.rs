@@ -0,0 +1,381 @@
+use std::fmt;
+
+use rand::Rng;
+
+///smoothing factor applied to every new latency sample of a container
+pub const LATENCY_EWMA_DECAY: f64 = 0.3;
+///points every container occupies on the consistent hash ring
+pub const CONSISTENT_HASH_VIRTUAL_NODES: usize = 128;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoadBalancerBehavior {
+    RoundRobin,
+    LeastOutstandingRequests,
+    WeightedRoundRobin,
+    PowerOfTwoChoices,
+    LatencyEwma,
+    ConsistentHash
+}
+
+impl fmt::Display for LoadBalancerBehavior {
+    fn fmt(&self, formatter:&mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RoundRobin => formatter.write_str("round_robin"),
+            Self::LeastOutstandingRequests => formatter.write_str("least_outstanding_requests"),
+            Self::WeightedRoundRobin => formatter.write_str("weighted_round_robin"),
+            Self::PowerOfTwoChoices => formatter.write_str("power_of_two_choices"),
+            Self::LatencyEwma => formatter.write_str("latency_ewma"),
+            Self::ConsistentHash => formatter.write_str("consistent_hash")
+        }
+    }
+}
+
+impl LoadBalancerBehavior {
+    ///returns the [type LoadBalancerBehavior] matching the stored behavior string
+    pub fn from_behavior(behavior:&str) -> Option<LoadBalancerBehavior> {
+        match behavior {
+            "round_robin" => Some(LoadBalancerBehavior::RoundRobin),
+            "least_outstanding_requests" => Some(LoadBalancerBehavior::LeastOutstandingRequests),
+            "weighted_round_robin" => Some(LoadBalancerBehavior::WeightedRoundRobin),
+            "power_of_two_choices" => Some(LoadBalancerBehavior::PowerOfTwoChoices),
+            "latency_ewma" => Some(LoadBalancerBehavior::LatencyEwma),
+            "consistent_hash" => Some(LoadBalancerBehavior::ConsistentHash),
+            _ => None
+        }
+    }
+
+    ///creates a fresh selection strategy for the behavior
+    pub fn strategy(&self) -> Box<dyn LoadBalancingStrategy> {
+        match self {
+            Self::RoundRobin => Box::new(RoundRobin { head: 0 }),
+            Self::LeastOutstandingRequests => Box::new(LeastOutstandingRequests { head: 0 }),
+            Self::WeightedRoundRobin => Box::new(WeightedRoundRobin { current_weights: vec![] }),
+            Self::PowerOfTwoChoices => Box::new(PowerOfTwoChoices {}),
+            Self::LatencyEwma => Box::new(LatencyEwma {}),
+            Self::ConsistentHash => Box::new(ConsistentHash { ring: vec![], ring_members: vec![], fallback: RoundRobin { head: 0 } })
+        }
+    }
+}
+
+///load snapshot of a single container used when selecting the next container
+#[derive(Clone, Debug, Default)]
+pub struct ContainerLoad {
+    pub container_id: String,
+    pub in_flight: usize,
+    pub weight: usize,
+    pub latency_ewma: Option<f64>,
+    pub is_ejected: bool //failed its health checks
+}
+
+///selects the index of the next container out of the candidates
+///
+/// candidates are in the same order as the containers of the load balancer
+/// and returns None only when there are no candidates.
+/// the affinity key is only used by behaviors that pin requests
+pub trait LoadBalancingStrategy: Send {
+    fn select(&mut self, candidates:&[ContainerLoad], affinity_key:Option<&str>) -> Option<usize>;
+}
+
+pub struct RoundRobin {
+    head: usize
+}
+
+impl LoadBalancingStrategy for RoundRobin {
+    fn select(&mut self, candidates:&[ContainerLoad], _affinity_key:Option<&str>) -> Option<usize> {
+        if candidates.is_empty() {
+            return None;
+        }
+        self.head = (self.head + 1) % candidates.len();
+        Some(self.head)
+    }
+}
+
+///picks the container with the least in-flight requests, ties are rotated
+pub struct LeastOutstandingRequests {
+    head: usize
+}
+
+impl LoadBalancingStrategy for LeastOutstandingRequests {
+    fn select(&mut self, candidates:&[ContainerLoad], _affinity_key:Option<&str>) -> Option<usize> {
+        let least_in_flight = candidates.iter().map(|candidate| candidate.in_flight).min()?;
+        self.head = (self.head + 1) % candidates.len();
+        //start from the head so equally loaded containers take turns
+        (0..candidates.len())
+            .map(|offset| (self.head + offset) % candidates.len())
+            .find(|index| candidates[*index].in_flight == least_in_flight)
+    }
+}
+
+///smooth weighted round robin, containers with a weight of 0 are only picked when every weight is 0
+pub struct WeightedRoundRobin {
+    current_weights: Vec<i64>
+}
+
+impl LoadBalancingStrategy for WeightedRoundRobin {
+    fn select(&mut self, candidates:&[ContainerLoad], _affinity_key:Option<&str>) -> Option<usize> {
+        if candidates.is_empty() {
+            return None;
+        }
+        if self.current_weights.len() != candidates.len() {
+            self.current_weights = vec![0; candidates.len()];
+        }
+        let total_weight: i64 = candidates.iter().map(|candidate| candidate.weight as i64).sum();
+        if total_weight == 0 {
+            return Some(0);
+        }
+        let mut selected_index = 0;
+        for (index, candidate) in candidates.iter().enumerate() {
+            self.current_weights[index] += candidate.weight as i64;
+            if self.current_weights[index] > self.current_weights[selected_index] {
+                selected_index = index;
+            }
+        }
+        self.current_weights[selected_index] -= total_weight;
+        Some(selected_index)
+    }
+}
+
+///samples two random containers and picks the one with less in-flight requests
+pub struct PowerOfTwoChoices {}
+
+impl LoadBalancingStrategy for PowerOfTwoChoices {
+    fn select(&mut self, candidates:&[ContainerLoad], _affinity_key:Option<&str>) -> Option<usize> {
+        match candidates.len() {
+            0 => None,
+            1 => Some(0),
+            candidate_count => {
+                let mut rng = rand::thread_rng();
+                let first = rng.gen_range(0..candidate_count);
+                //offsetting by a non zero amount guarantees two distinct containers
+                let second = (first + rng.gen_range(1..candidate_count)) % candidate_count;
+                if candidates[second].in_flight < candidates[first].in_flight {
+                    Some(second)
+                }else{
+                    Some(first)
+                }
+            }
+        }
+    }
+}
+
+///picks the container with the lowest latency ewma weighted by its in-flight requests
+///
+/// containers without a latency sample are preferred so they get measured
+pub struct LatencyEwma {}
+
+impl LoadBalancingStrategy for LatencyEwma {
+    fn select(&mut self, candidates:&[ContainerLoad], _affinity_key:Option<&str>) -> Option<usize> {
+        candidates.iter().enumerate().map(|(index, candidate)| {
+            let score = candidate.latency_ewma.unwrap_or(0.0) * (candidate.in_flight + 1) as f64;
+            (index, score)
+        }).min_by(|(_, left), (_, right)| left.total_cmp(right)).map(|(index, _)| index)
+    }
+}
+
+///pins an affinity key to the same container for as long as it exists
+///
+/// containers are placed on a hash ring so adding or removing one only remaps the keys next to it.
+/// requests without an affinity key are spread with round robin
+pub struct ConsistentHash {
+    ring: Vec<(u64, usize)>, //(point, candidate index) sorted by point
+    ring_members: Vec<String>, //container ids the ring was built from
+    fallback: RoundRobin
+}
+
+impl ConsistentHash {
+    fn rebuild_ring(&mut self, candidates:&[ContainerLoad]) {
+        self.ring = candidates.iter().enumerate().flat_map(|(index, candidate)| {
+            (0..CONSISTENT_HASH_VIRTUAL_NODES).map(move |virtual_node| (hash_key(&format!("{}#{}", candidate.container_id, virtual_node)), index))
+        }).collect();
+        self.ring.sort_unstable();
+        self.ring_members = candidates.iter().map(|candidate| candidate.container_id.clone()).collect();
+    }
+}
+
+impl LoadBalancingStrategy for ConsistentHash {
+    fn select(&mut self, candidates:&[ContainerLoad], affinity_key:Option<&str>) -> Option<usize> {
+        if candidates.is_empty() {
+            return None;
+        }
+        let affinity_key = match affinity_key {
+            Some(affinity_key) => affinity_key,
+            None => return self.fallback.select(candidates, None)
+        };
+        let is_same_members = self.ring_members.len() == candidates.len()
+            && self.ring_members.iter().zip(candidates.iter()).all(|(member, candidate)| member == &candidate.container_id);
+        if !is_same_members {
+            self.rebuild_ring(candidates);
+        }
+        let key_point = hash_key(affinity_key);
+        let ring_index = self.ring.partition_point(|(point, _)| *point < key_point) % self.ring.len();
+        Some(self.ring[ring_index].1)
+    }
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+///FNV-1a hash of the key, stable across builds and restarts so affinity keys keep their container
+/// 
+/// keys differing only in their last bytes, such as the virtual nodes of a container, are spread over the ring by the murmur3 finalizer
+pub fn hash_key(key:&str) -> u64 {
+    let hash = key.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME));
+    let hash = (hash ^ (hash >> 33)).wrapping_mul(0xff51afd7ed558ccd);
+    let hash = (hash ^ (hash >> 33)).wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+///returns the new latency ewma after adding the latency sample in milliseconds
+pub fn next_latency_ewma(latency_ewma:Option<f64>, latency_sample:f64) -> f64 {
+    match latency_ewma {
+        Some(latency_ewma) => latency_ewma + LATENCY_EWMA_DECAY * (latency_sample - latency_ewma),
+        None => latency_sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(count:usize) -> Vec<ContainerLoad> {
+        (0..count).map(|index| ContainerLoad { container_id: format!("container-{}", index), weight: 1, ..Default::default() }).collect()
+    }
+
+    fn selected_ids(strategy:&mut dyn LoadBalancingStrategy, candidates:&[ContainerLoad], keys:&[String]) -> Vec<String> {
+        keys.iter().map(|key| candidates[strategy.select(candidates, Some(key)).unwrap()].container_id.clone()).collect()
+    }
+
+    #[test]
+    fn round_robin_cycles_through_candidates() {
+        let mut strategy = LoadBalancerBehavior::RoundRobin.strategy();
+        let candidates = candidates(3);
+        let selections: Vec<usize> = (0..6).map(|_| strategy.select(&candidates, None).unwrap()).collect();
+        assert_eq!(selections, vec![1, 2, 0, 1, 2, 0]);
+        assert_eq!(strategy.select(&[], None), None);
+    }
+
+    #[test]
+    fn least_outstanding_requests_picks_least_loaded() {
+        let mut strategy = LoadBalancerBehavior::LeastOutstandingRequests.strategy();
+        let mut candidates = candidates(3);
+        candidates[0].in_flight = 4;
+        candidates[1].in_flight = 1;
+        candidates[2].in_flight = 3;
+        for _ in 0..5 {
+            assert_eq!(strategy.select(&candidates, None), Some(1));
+        }
+    }
+
+    #[test]
+    fn least_outstanding_requests_rotates_ties() {
+        let mut strategy = LoadBalancerBehavior::LeastOutstandingRequests.strategy();
+        let candidates = candidates(3);
+        let selections: Vec<usize> = (0..3).map(|_| strategy.select(&candidates, None).unwrap()).collect();
+        assert_eq!(selections, vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn weighted_round_robin_follows_weights() {
+        let mut strategy = LoadBalancerBehavior::WeightedRoundRobin.strategy();
+        let mut candidates = candidates(3);
+        candidates[0].weight = 5;
+        candidates[1].weight = 1;
+        candidates[2].weight = 1;
+        let mut counts = [0; 3];
+        for _ in 0..70 {
+            counts[strategy.select(&candidates, None).unwrap()] += 1;
+        }
+        assert_eq!(counts, [50, 10, 10]);
+    }
+
+    #[test]
+    fn weighted_round_robin_spreads_equal_weights() {
+        let mut strategy = LoadBalancerBehavior::WeightedRoundRobin.strategy();
+        let candidates = candidates(3);
+        let mut counts = [0; 3];
+        for _ in 0..30 {
+            counts[strategy.select(&candidates, None).unwrap()] += 1;
+        }
+        assert_eq!(counts, [10, 10, 10]);
+    }
+
+    #[test]
+    fn weighted_round_robin_skips_zero_weights() {
+        let mut strategy = LoadBalancerBehavior::WeightedRoundRobin.strategy();
+        let mut candidates = candidates(3);
+        candidates[0].weight = 0;
+        candidates[2].weight = 0;
+        for _ in 0..5 {
+            assert_eq!(strategy.select(&candidates, None), Some(1));
+        }
+        for candidate in candidates.iter_mut() {
+            candidate.weight = 0;
+        }
+        assert_eq!(strategy.select(&candidates, None), Some(0));
+    }
+
+    #[test]
+    fn power_of_two_choices_never_picks_the_most_loaded() {
+        let mut strategy = LoadBalancerBehavior::PowerOfTwoChoices.strategy();
+        let mut candidates = candidates(2);
+        candidates[0].in_flight = 10;
+        for _ in 0..50 {
+            assert_eq!(strategy.select(&candidates, None), Some(1));
+        }
+        assert_eq!(strategy.select(&candidates[..1], None), Some(0));
+        assert_eq!(strategy.select(&[], None), None);
+    }
+
+    #[test]
+    fn latency_ewma_prefers_unmeasured_then_fastest() {
+        let mut strategy = LoadBalancerBehavior::LatencyEwma.strategy();
+        let mut candidates = candidates(3);
+        candidates[0].latency_ewma = Some(20.0);
+        candidates[1].latency_ewma = Some(5.0);
+        assert_eq!(strategy.select(&candidates, None), Some(2));
+        candidates[2].latency_ewma = Some(10.0);
+        assert_eq!(strategy.select(&candidates, None), Some(1));
+        //in-flight requests weigh on the latency
+        candidates[1].in_flight = 3;
+        assert_eq!(strategy.select(&candidates, None), Some(2));
+    }
+
+    #[test]
+    fn next_latency_ewma_moves_towards_sample() {
+        assert_eq!(next_latency_ewma(None, 12.0), 12.0);
+        let latency_ewma = next_latency_ewma(Some(10.0), 20.0);
+        assert!((latency_ewma - 13.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn consistent_hash_pins_affinity_keys() {
+        let mut strategy = LoadBalancerBehavior::ConsistentHash.strategy();
+        let candidates = candidates(4);
+        let keys: Vec<String> = (0..100).map(|index| format!("client-{}", index)).collect();
+        assert_eq!(selected_ids(strategy.as_mut(), &candidates, &keys), selected_ids(strategy.as_mut(), &candidates, &keys));
+    }
+
+    #[test]
+    fn hash_key_is_stable_across_builds() {
+        assert_eq!(hash_key("client-1"), 0xf93bd2c16ac6882d);
+    }
+
+    #[test]
+    fn consistent_hash_remaps_a_fraction_of_keys() {
+        let mut strategy = LoadBalancerBehavior::ConsistentHash.strategy();
+        let keys: Vec<String> = (0..2000).map(|index| format!("client-{}", index)).collect();
+        let before = selected_ids(strategy.as_mut(), &candidates(4), &keys);
+
+        let after_add = selected_ids(strategy.as_mut(), &candidates(5), &keys);
+        let remapped = before.iter().zip(after_add.iter()).filter(|(before, after)| before != after).count();
+        //only the keys taken over by the new container move, about a fifth of them
+        assert!(after_add.iter().zip(before.iter()).all(|(after, before)| after == before || after == "container-4"));
+        assert!(remapped > 0 && remapped < keys.len() * 2 / 5, "remapped {} keys", remapped);
+
+        let after_remove = selected_ids(strategy.as_mut(), &candidates(3), &keys);
+        let remapped = before.iter().zip(after_remove.iter()).filter(|(before, after)| before != after).count();
+        //only the keys of the removed container move
+        assert!(before.iter().zip(after_remove.iter()).all(|(before, after)| before == after || before == "container-3"));
+        assert!(remapped < keys.len() * 2 / 5, "remapped {} keys", remapped);
+    }
+}
@@ -1,4 +1,4 @@
-use std::sync::OnceLock;
+use std::{fmt, sync::OnceLock};
 use mongodb::{options::ClientOptions, Client, Collection, Database};
 
 pub static DATABASE:OnceLock<Database> = OnceLock::new();
@@ -8,11 +8,11 @@ pub async fn connect()-> Database{
 	
 	let options:ClientOptions = ClientOptions::parse(std::env::var("DATABASE_URI").unwrap()).await.unwrap();
 	let client = Client::with_options(options).unwrap();
-	let database = client.database(std::env::var("DATABASE_NAME").unwrap().as_str());
-	return database;
+	client.database(std::env::var("DATABASE_NAME").unwrap().as_str())
     
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub enum DBCollection {
     IMAGES,
     ROUTES,
@@ -20,24 +20,24 @@ pub enum DBCollection {
     CONTAINERS,
 }
 
-impl ToString for DBCollection {
-    fn to_string(&self) -> String {
-        match &self {
-            &Self::IMAGES => "images".to_string(),
-            &Self::ROUTES => "routes".to_string(),
-            &Self::LOADBALANCERS => "load_balancers".to_string(),
-            &Self::CONTAINERS => "containers".to_string(),
-        }    
+impl fmt::Display for DBCollection {
+    fn fmt(&self, formatter:&mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IMAGES => formatter.write_str("images"),
+            Self::ROUTES => formatter.write_str("routes"),
+            Self::LOADBALANCERS => formatter.write_str("load_balancers"),
+            Self::CONTAINERS => formatter.write_str("containers"),
+        }
     }
 }
 
 impl DBCollection {
     pub async fn collection<T>(&self)->Collection<T>{
-        match &self {
-            &Self::IMAGES => DATABASE.get().unwrap().collection::<T>(DBCollection::IMAGES.to_string().as_str()),
-            &Self::ROUTES => DATABASE.get().unwrap().collection::<T>(DBCollection::ROUTES.to_string().as_str()),
-            &Self::LOADBALANCERS => DATABASE.get().unwrap().collection::<T>(DBCollection::LOADBALANCERS.to_string().as_str()),
-            &Self::CONTAINERS => DATABASE.get().unwrap().collection::<T>(DBCollection::CONTAINERS.to_string().as_str()),
+        match self {
+            Self::IMAGES => DATABASE.get().unwrap().collection::<T>(DBCollection::IMAGES.to_string().as_str()),
+            Self::ROUTES => DATABASE.get().unwrap().collection::<T>(DBCollection::ROUTES.to_string().as_str()),
+            Self::LOADBALANCERS => DATABASE.get().unwrap().collection::<T>(DBCollection::LOADBALANCERS.to_string().as_str()),
+            Self::CONTAINERS => DATABASE.get().unwrap().collection::<T>(DBCollection::CONTAINERS.to_string().as_str()),
         }
     }
 }
\ No newline at end of file
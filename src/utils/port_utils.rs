@@ -0,0 +1,79 @@
+use std::{collections::{HashMap, HashSet}, env, sync::{Arc, OnceLock}};
+
+use bollard::container::ListContainersOptions;
+use mongodb::bson::doc;
+use tokio::sync::Mutex;
+
+use crate::models::docker_models;
+
+use super::{docker_utils::DOCKER_CONNECTION, mongodb_utils::DBCollection};
+
+///host ports handed out by this process, bound to the docker_container_id once the container is created
+pub type PortReservations = HashMap<usize, Option<String>>;
+pub static PORT_RESERVATIONS:OnceLock<Arc<Mutex<PortReservations>>> = OnceLock::new();
+
+///returns the host port range of containers as STARTING_PORT..ENDING_PORT
+pub fn port_range() -> Result<(usize, usize), String> {
+    let parse_port = |name:&str| env::var(name).ok().and_then(|port| port.parse::<usize>().ok()).ok_or_else(|| format!("{} is not set to a valid port", name));
+    let (starting_port, ending_port) = (parse_port("STARTING_PORT")?, parse_port("ENDING_PORT")?);
+    if starting_port >= ending_port {
+        return Err(format!("STARTING_PORT {} must be lower than ENDING_PORT {}", starting_port, ending_port));
+    }
+    Ok((starting_port, ending_port))
+}
+
+///reserves the lowest host port in the range that is not in use
+///
+/// ports recorded in the containers collection, published by docker or reserved by this process are in use.
+/// the reservation lock is held while looking up ports so concurrent container creations never get the same port
+pub async fn reserve_port() -> Result<usize, String> {
+    let (starting_port, ending_port) = port_range()?;
+    let mut reservations_mutex = PORT_RESERVATIONS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+    let mut ports_in_use = used_ports().await?;
+    ports_in_use.extend(reservations_mutex.keys());
+    let port = (starting_port..ending_port).find(|port| !ports_in_use.contains(port))
+        .ok_or_else(|| format!("No free host port left between {} and {}, {} ports are in use", starting_port, ending_port, ending_port - starting_port))?;
+    reservations_mutex.insert(port, None);
+    Ok(port)
+}
+
+///ties the reserved port to the container it was published on
+pub async fn bind_port(port:usize, docker_container_id:&str) {
+    let mut reservations_mutex = PORT_RESERVATIONS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+    reservations_mutex.insert(port, Some(docker_container_id.to_string()));
+}
+
+///releases a reserved port, used when the container could not be created
+pub async fn release_port(port:usize) {
+    PORT_RESERVATIONS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await.remove(&port);
+}
+
+///releases the ports bound to the container
+pub async fn release_container_ports(docker_container_id:&str) {
+    let mut reservations_mutex = PORT_RESERVATIONS.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+    reservations_mutex.retain(|_, bound_container_id| bound_container_id.as_deref() != Some(docker_container_id));
+}
+
+///returns the host ports of the containers collection and the ports docker publishes
+async fn used_ports() -> Result<HashSet<usize>, String> {
+    let mut ports_in_use = HashSet::new();
+    //containers on a docker network do not hold a host port
+    let mut cursor = DBCollection::CONTAINERS.collection::<docker_models::Container>().await.find(doc!{ "network": null }, None).await
+        .map_err(|_| "Cannot fetch the ports of the containers collection".to_string())?;
+    while let Ok(true) = cursor.advance().await {
+        if let Ok(container) = cursor.deserialize_current() {
+            ports_in_use.insert(container.public_port);
+        }
+    }
+    let docker = DOCKER_CONNECTION.get().unwrap();
+    let container_summaries = docker.list_containers(Some(ListContainersOptions::<String> { all: true, ..Default::default() })).await
+        .map_err(|_| "Cannot fetch the ports published by docker".to_string())?;
+    for container_summary in container_summaries.iter() {
+        for port in container_summary.ports.iter().flatten() {
+            if let Some(public_port) = port.public_port {
+                ports_in_use.insert(public_port as usize);
+            }
+        }
+    }
+    Ok(ports_in_use)
+}
@@ -0,0 +1,108 @@
+use std::{collections::HashMap, env, sync::{Arc, OnceLock}, time::Duration};
+
+use tokio::{net::TcpStream, sync::{watch, Mutex}, time::{sleep, timeout, Instant}};
+
+use crate::models::docker_models::{BackendConfig, ReadinessConfig};
+
+use super::upstream_utils::upstream_clients;
+
+const INITIAL_PROBE_BACKOFF: Duration = Duration::from_millis(50);
+const MAXIMUM_PROBE_BACKOFF: Duration = Duration::from_secs(1);
+const PROBE_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Readiness {
+    Pending,
+    Ready,
+    TimedOut
+}
+
+///readiness of containers that are still being probed keyed by docker_container_id
+pub type ReadinessGates = HashMap<String, watch::Receiver<Readiness>>;
+pub static READINESS_GATES:OnceLock<Arc<Mutex<ReadinessGates>>> = OnceLock::new();
+
+///returns the start timeout of the route, defaults to MAX_TIME_RETRY seconds
+pub fn start_timeout(readiness:Option<&ReadinessConfig>) -> Duration {
+    let default_start_timeout = env::var("MAX_TIME_RETRY").ok().and_then(|max_time| max_time.parse::<u64>().ok()).unwrap_or(30);
+    Duration::from_secs(readiness.and_then(|readiness| readiness.start_timeout).unwrap_or(default_start_timeout))
+}
+
+///waits until the container at the upstream base url answers a probe
+///
+/// is_started opens a gate for a container that was just started, every request to the same container
+/// waits on that gate and is released together once the probe succeeds or the start timeout passes.
+/// containers without an open gate are treated as ready
+pub async fn wait_until_ready(docker_container_id:&String, upstream:&str, readiness:Option<&ReadinessConfig>, backend:Option<&BackendConfig>, is_started:bool) -> Readiness {
+    let mut readiness_receiver = {
+        let mut gates_mutex = READINESS_GATES.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await;
+        match gates_mutex.get(docker_container_id) {
+            Some(readiness_receiver) => readiness_receiver.clone(),
+            None if is_started => {
+                let (readiness_sender, readiness_receiver) = watch::channel(Readiness::Pending);
+                gates_mutex.insert(docker_container_id.clone(), readiness_receiver.clone());
+                tokio::spawn(probe_until_ready(docker_container_id.clone(), upstream.to_string(), readiness.cloned(), backend.cloned(), readiness_sender));
+                readiness_receiver
+            },
+            None => return Readiness::Ready
+        }
+    };
+    let readiness = match readiness_receiver.wait_for(|readiness| *readiness != Readiness::Pending).await {
+        Ok(readiness) => *readiness,
+        Err(_) => Readiness::TimedOut
+    };
+    readiness
+}
+
+///returns true while requests to the container wait on its readiness gate
+pub async fn is_pending(docker_container_id:&String) -> bool {
+    READINESS_GATES.get_or_init(|| Arc::new(Mutex::new(HashMap::new()))).lock().await.contains_key(docker_container_id)
+}
+
+async fn probe_until_ready(docker_container_id:String, upstream:String, readiness:Option<ReadinessConfig>, backend:Option<BackendConfig>, readiness_sender:watch::Sender<Readiness>){
+    let deadline = Instant::now() + start_timeout(readiness.as_ref());
+    let probe_path = readiness.as_ref().and_then(|readiness| readiness.path.clone());
+    let mut backoff = INITIAL_PROBE_BACKOFF;
+    println!("[PROCESS] Waiting for container {} to become ready", &docker_container_id);
+    let result = loop {
+        if probe(&upstream, probe_path.as_deref(), backend.as_ref(), PROBE_ATTEMPT_TIMEOUT).await {
+            break Readiness::Ready;
+        }
+        if Instant::now() + backoff >= deadline {
+            break Readiness::TimedOut;
+        }
+        sleep(backoff).await;
+        backoff = (backoff * 2).min(MAXIMUM_PROBE_BACKOFF);
+    };
+    println!("[PROCESS] Container {} readiness: {:?}", &docker_container_id, result);
+    READINESS_GATES.get().unwrap().lock().await.remove(&docker_container_id);
+    let _ = readiness_sender.send(result);
+}
+
+///http probes succeed on any non 5xx response, tcp probes on an accepted connection
+/// 
+/// http probes go through the client of the backend config so they use the same scheme and tls settings as requests
+pub async fn probe(upstream:&str, probe_path:Option<&str>, backend:Option<&BackendConfig>, probe_timeout:Duration) -> bool {
+    match probe_path {
+        Some(probe_path) => {
+            let (client, upstream) = match upstream_clients().backend_client(backend, upstream, false).await {
+                Ok(backend_client) => backend_client,
+                Err(_) => return false
+            };
+            match client.get(format!("{}{}", upstream, probe_path)).timeout(probe_timeout).send().await {
+                Ok(response) => !response.status().is_server_error(),
+                Err(_) => false
+            }
+        },
+        None => {
+            let upstream_url = match reqwest::Url::parse(upstream) {
+                Ok(upstream_url) => upstream_url,
+                Err(_) => return false
+            };
+            let (host, port) = match (upstream_url.host_str(), upstream_url.port_or_known_default()) {
+                (Some(host), Some(port)) => (host.to_string(), port),
+                _ => return false
+            };
+            matches!(timeout(probe_timeout, TcpStream::connect((host.as_str(), port))).await, Ok(Ok(_)))
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use std::net::SocketAddr;
+
+use hyper::{header::{HeaderName, HeaderValue, CONNECTION, COOKIE, FORWARDED, HOST, PROXY_AUTHENTICATE, PROXY_AUTHORIZATION, TE, TRAILER, TRANSFER_ENCODING, UPGRADE}, HeaderMap, Method, Uri};
+use mongodb::bson::oid::ObjectId;
+
+use crate::models::docker_models::{AffinityConfig, AffinitySource};
+
+pub const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+pub const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+pub const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+pub const X_FORWARDED_PREFIX: HeaderName = HeaderName::from_static("x-forwarded-prefix");
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+pub const X_ROUTE_PARAM_PREFIX: &str = "x-route-param-";
+
+///headers that only apply to a single connection and are never forwarded, rfc 9110 section 7.6.1
+const HOP_BY_HOP_HEADERS: [HeaderName; 9] = [
+    CONNECTION,
+    HeaderName::from_static("keep-alive"),
+    HeaderName::from_static("proxy-connection"),
+    PROXY_AUTHENTICATE,
+    PROXY_AUTHORIZATION,
+    TE,
+    TRAILER,
+    TRANSFER_ENCODING,
+    UPGRADE
+];
+
+///returns the value of the first cookie named cookie_name across every cookie header
+pub fn cookie_value(headers:&HeaderMap, cookie_name:&str) -> Option<String> {
+    headers.get_all(COOKIE).iter()
+        .filter_map(|header_value| header_value.to_str().ok())
+        .flat_map(|cookie_header| cookie_header.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == cookie_name)
+        .map(|(_, value)| value.to_string())
+}
+
+///returns the values of every query parameter named name, compared as sent without percent-decoding
+pub fn query_values<'a>(uri:&'a Uri, name:&str) -> Vec<&'a str> {
+    uri.query().unwrap_or_default().split('&')
+        .map(|parameter| parameter.split_once('=').unwrap_or((parameter, "")))
+        .filter(|(parameter_name, _)| *parameter_name == name)
+        .map(|(_, value)| value)
+        .collect()
+}
+
+///returns the affinity key of the request based on the source of the affinity config
+pub fn affinity_key(affinity:&AffinityConfig, headers:&HeaderMap, client_address:&SocketAddr) -> Option<String> {
+    match affinity.source {
+        AffinitySource::Cookie => cookie_value(headers, affinity.cookie_name()),
+        AffinitySource::Header => {
+            let header_name = affinity.header_name.as_deref()?;
+            headers.get(header_name).and_then(|header_value| header_value.to_str().ok()).map(String::from)
+        },
+        AffinitySource::ClientIp => Some(client_address.ip().to_string())
+    }
+}
+
+///returns a new affinity key and its set-cookie header value when the affinity config issues its own cookie
+pub fn issue_affinity_cookie(affinity:&AffinityConfig) -> Option<(String, HeaderValue)> {
+    if affinity.source != AffinitySource::Cookie || !affinity.issue_cookie.unwrap_or(false) {
+        return None;
+    }
+    let affinity_value = ObjectId::new().to_hex();
+    let set_cookie = HeaderValue::from_str(&format!("{}={}; Path=/; HttpOnly", affinity.cookie_name(), affinity_value)).ok()?;
+    Some((affinity_value, set_cookie))
+}
+
+///returns true when the request asks to switch protocols, such as a websocket handshake
+pub fn is_upgrade_request(headers:&HeaderMap) -> bool {
+    headers.contains_key(UPGRADE) && headers.get_all(CONNECTION).iter()
+        .filter_map(|header_value| header_value.to_str().ok())
+        .flat_map(|connection| connection.split(','))
+        .any(|option| option.trim().eq_ignore_ascii_case("upgrade"))
+}
+
+///returns true for methods that can be sent more than once without changing the result
+pub fn is_idempotent_method(method:&Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE)
+}
+
+///removes the hop-by-hop headers and the headers named by the connection header
+/// 
+/// te: trailers is kept since grpc destinations require it
+pub fn remove_hop_by_hop_headers(headers:&mut HeaderMap) {
+    let connection_headers: Vec<HeaderName> = headers.get_all(CONNECTION).iter()
+        .filter_map(|header_value| header_value.to_str().ok())
+        .flat_map(|connection| connection.split(','))
+        .filter_map(|option| HeaderName::from_bytes(option.trim().as_bytes()).ok())
+        .collect();
+    let accepts_trailers = headers.get_all(TE).iter()
+        .filter_map(|header_value| header_value.to_str().ok())
+        .flat_map(|te| te.split(','))
+        .any(|option| option.trim().eq_ignore_ascii_case("trailers"));
+    for header_name in connection_headers.iter().chain(HOP_BY_HOP_HEADERS.iter()) {
+        headers.remove(header_name);
+    }
+    if accepts_trailers {
+        headers.insert(TE, HeaderValue::from_static("trailers"));
+    }
+}
+
+///appends the client to x-forwarded-for and forwarded and sets the x-forwarded-proto, host and prefix of the request
+/// 
+/// the orchestrator only listens over https so the forwarded proto is always https
+pub fn set_forwarded_headers(headers:&mut HeaderMap, client_address:&SocketAddr, host:Option<&str>, prefix:Option<&str>) {
+    let client_ip = client_address.ip().to_string();
+    let forwarded_for = match headers.get(X_FORWARDED_FOR).and_then(|header_value| header_value.to_str().ok()) {
+        Some(forwarded_for) => format!("{}, {}", forwarded_for, client_ip),
+        None => client_ip
+    };
+    if let Ok(forwarded_for) = HeaderValue::from_str(&forwarded_for) {
+        headers.insert(X_FORWARDED_FOR, forwarded_for);
+    }
+    headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static("https"));
+    //ipv6 addresses are quoted and bracketed as forwarded node names
+    let mut forwarded = match client_address {
+        SocketAddr::V4(client_address) => format!("for={};proto=https", client_address.ip()),
+        SocketAddr::V6(client_address) => format!("for=\"[{}]\";proto=https", client_address.ip())
+    };
+    if let Some(host) = host {
+        if let Ok(forwarded_host) = HeaderValue::from_str(host) {
+            headers.insert(X_FORWARDED_HOST, forwarded_host);
+            forwarded = format!("{};host=\"{}\"", forwarded, host);
+        }
+    }
+    if let Some(prefix) = prefix {
+        if let Ok(forwarded_prefix) = HeaderValue::from_str(prefix) {
+            headers.insert(X_FORWARDED_PREFIX, forwarded_prefix);
+        }
+    }
+    if let Ok(forwarded) = HeaderValue::from_str(&forwarded) {
+        headers.append(FORWARDED, forwarded);
+    }
+}
+
+///returns the host the client addressed as sent, http/2 clients send it as the authority of the uri
+pub fn request_host(headers:&HeaderMap, uri:&Uri) -> Option<String> {
+    headers.get(HOST).and_then(|host| host.to_str().ok())
+        .or_else(|| uri.authority().map(|authority| authority.as_str()))
+        .map(String::from)
+}
+
+///lowercases the host and removes its port and trailing dot so it can be compared with route hosts and sni server names
+pub fn normalize_host(host:&str) -> String {
+    let host = host.trim();
+    //the port of an ipv6 address follows its closing bracket
+    let host_end = if host.starts_with('[') {
+        host.find(']').map_or(host.len(), |bracket_index| bracket_index + 1)
+    }else{
+        host.find(':').unwrap_or(host.len())
+    };
+    host[..host_end].trim_end_matches('.').to_ascii_lowercase()
+}
+
+///replaces the x-route-param headers of the client with the parameters captured by the address of the route
+pub fn set_route_param_headers(headers:&mut HeaderMap, params:&[(String, String)]) {
+    let client_param_headers: Vec<HeaderName> = headers.keys()
+        .filter(|header_name| header_name.as_str().starts_with(X_ROUTE_PARAM_PREFIX))
+        .cloned()
+        .collect();
+    for header_name in client_param_headers {
+        headers.remove(header_name);
+    }
+    for (name, value) in params {
+        let header_name = HeaderName::from_bytes(format!("{}{}", X_ROUTE_PARAM_PREFIX, name.to_ascii_lowercase()).as_bytes());
+        if let (Ok(header_name), Ok(header_value)) = (header_name, HeaderValue::from_str(value)) {
+            headers.insert(header_name, header_value);
+        }
+    }
+}
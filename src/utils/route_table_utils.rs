@@ -0,0 +1,707 @@
+use std::{cmp::Reverse, collections::HashMap, sync::{Arc, OnceLock}, time::Duration};
+
+use futures_util::StreamExt;
+use hyper::{header::HeaderName, HeaderMap, Method, Uri};
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use regex::Regex;
+use tokio::sync::RwLock;
+
+use crate::models::docker_models::{Image, PathRewriteMode, Route, RoutePredicates, ValuePredicate};
+
+use super::{mongodb_utils::DBCollection, request_utils, upstream_utils::upstream_clients};
+
+const CHANGE_STREAM_RETRY: Duration = Duration::from_secs(5);
+
+pub static ROUTE_TABLE:OnceLock<RwLock<Arc<RouteTable>>> = OnceLock::new();
+
+///a stored route together with the docker image of container routes, resolved when the table is built
+pub struct RouteEntry {
+    pub route: Route,
+    pub docker_image_id: Option<String>,
+    address_pattern: Option<Regex>,
+    pub rewrite_pattern: Option<Regex>, //pattern of regex rewrites
+    predicates: Option<CompiledPredicates>
+}
+
+impl RouteEntry {
+    pub fn new(route:Route, docker_image_id:Option<String>) -> Result<RouteEntry, String> {
+        let address_pattern = address_pattern(&route.address).transpose().map_err(|error| format!("Invalid address pattern: {}", error))?;
+        let rewrite_pattern = route.rewrite.as_ref()
+            .filter(|rewrite| rewrite.mode == PathRewriteMode::Regex)
+            .and_then(|rewrite| rewrite.pattern.as_deref())
+            .map(Regex::new).transpose().map_err(|error| format!("Invalid rewrite pattern: {}", error))?;
+        let predicates = route.predicates.as_ref().map(CompiledPredicates::new).transpose()?;
+        Ok(RouteEntry { route, docker_image_id, address_pattern, rewrite_pattern, predicates })
+    }
+}
+
+///segment of a route address, :name matches any single segment and *name the rest of the path
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AddressSegment<'a> {
+    Literal(&'a str),
+    Param(&'a str),
+    CatchAll(&'a str)
+}
+
+pub fn address_segments(address:&str) -> impl Iterator<Item = AddressSegment<'_>> {
+    path_segments(address).map(|segment| {
+        if let Some(name) = segment.strip_prefix(':') {
+            AddressSegment::Param(name)
+        }else if let Some(name) = segment.strip_prefix('*') {
+            AddressSegment::CatchAll(name)
+        }else{
+            AddressSegment::Literal(segment)
+        }
+    })
+}
+
+///returns the regex of addresses starting with ~, anchored to the start of the path
+pub fn address_pattern(address:&str) -> Option<Result<Regex, regex::Error>> {
+    address.strip_prefix('~').map(|pattern| Regex::new(&format!("^(?:{})", pattern)))
+}
+
+///returns the address with its parameter names and trailing catch-all removed,
+/// addresses with the same key match the same paths
+fn address_key(address:&str) -> String {
+    if address.starts_with('~') {
+        return address.to_string();
+    }
+    address_segments(address)
+        .filter_map(|segment| match segment {
+            AddressSegment::Literal(literal) => Some(literal),
+            AddressSegment::Param(_) => Some(":"),
+            AddressSegment::CatchAll(_) => None
+        })
+        .collect::<Vec<&str>>()
+        .join("/")
+}
+
+///returns true when routes with the predicates match the same requests, the priority only orders them
+fn same_predicates(predicates:Option<&RoutePredicates>, other_predicates:Option<&RoutePredicates>) -> bool {
+    let without_priority = |predicates:Option<&RoutePredicates>| predicates
+        .filter(|predicates| predicates.specificity() > 0)
+        .map(|predicates| RoutePredicates { priority: 0, ..predicates.clone() });
+    without_priority(predicates) == without_priority(other_predicates)
+}
+
+///parameters captured by the address of the matched route
+#[derive(Clone, Debug, Default)]
+pub struct RouteParams {
+    pub matched_path: String, //part of the path matched by the address, without the catch-all
+    pub values: Vec<(String, String)>
+}
+
+impl RouteParams {
+    pub fn get(&self, name:&str) -> Option<&str> {
+        self.values.iter().find(|(param_name, _)| param_name == name).map(|(_, value)| value.as_str())
+    }
+
+    ///replaces the {name} placeholders of the template with the captured parameters,
+    /// ${name} is left for the groups of regex rewrites and unknown names are kept as is
+    pub fn expand(&self, template:&str) -> String {
+        let mut expanded = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(open_index) = rest.find('{') {
+            expanded.push_str(&rest[..open_index]);
+            let placeholder = &rest[open_index + 1..];
+            let param = placeholder.split_once('}')
+                .filter(|_| !expanded.ends_with('$'))
+                .and_then(|(name, remainder)| Some((self.get(name)?, remainder)));
+            match param {
+                Some((value, remainder)) => {
+                    expanded.push_str(value);
+                    rest = remainder;
+                },
+                None => {
+                    expanded.push('{');
+                    rest = placeholder;
+                }
+            }
+        }
+        expanded.push_str(rest);
+        expanded
+    }
+}
+
+///request the route table is matched against
+pub struct RouteRequest<'a> {
+    pub host: Option<&'a str>,
+    pub method: &'a Method,
+    pub uri: &'a Uri,
+    pub headers: &'a HeaderMap
+}
+
+///result of matching a request against the route table
+pub enum RouteMatch {
+    Found(Arc<RouteEntry>, RouteParams),
+    //routes match the request except for its method, holds the methods they allow
+    MethodNotAllowed(Vec<Method>),
+    NotFound
+}
+
+enum ValueMatcher {
+    Present,
+    Exact(String),
+    Pattern(Regex)
+}
+
+impl ValueMatcher {
+    fn new(predicate:&ValuePredicate) -> Result<ValueMatcher, String> {
+        match (&predicate.value, &predicate.pattern) {
+            (Some(value), _) => Ok(ValueMatcher::Exact(value.clone())),
+            (None, Some(pattern)) => Regex::new(pattern).map(ValueMatcher::Pattern).map_err(|error| format!("Invalid pattern for {}: {}", predicate.name, error)),
+            (None, None) => Ok(ValueMatcher::Present)
+        }
+    }
+
+    fn is_match(&self, value:&str) -> bool {
+        match self {
+            ValueMatcher::Present => true,
+            ValueMatcher::Exact(expected_value) => value == expected_value,
+            ValueMatcher::Pattern(pattern) => pattern.is_match(value)
+        }
+    }
+}
+
+///route predicates with their header names parsed and patterns compiled once per route table
+struct CompiledPredicates {
+    methods: Vec<Method>,
+    headers: Vec<(HeaderName, ValueMatcher)>,
+    query: Vec<(String, ValueMatcher)>,
+    cookies: Vec<(String, ValueMatcher)>
+}
+
+impl CompiledPredicates {
+    fn new(predicates:&RoutePredicates) -> Result<CompiledPredicates, String> {
+        let methods = predicates.methods.iter()
+            .map(|method| Method::from_bytes(method.to_ascii_uppercase().as_bytes()).map_err(|_| format!("Invalid method {}", method)))
+            .collect::<Result<Vec<Method>, String>>()?;
+        let headers = predicates.headers.iter()
+            .map(|predicate| {
+                let header_name = HeaderName::from_bytes(predicate.name.as_bytes()).map_err(|_| format!("Invalid header name {}", predicate.name))?;
+                Ok((header_name, ValueMatcher::new(predicate)?))
+            })
+            .collect::<Result<Vec<(HeaderName, ValueMatcher)>, String>>()?;
+        let query = predicates.query.iter()
+            .map(|predicate| Ok((predicate.name.clone(), ValueMatcher::new(predicate)?)))
+            .collect::<Result<Vec<(String, ValueMatcher)>, String>>()?;
+        let cookies = predicates.cookies.iter()
+            .map(|predicate| Ok((predicate.name.clone(), ValueMatcher::new(predicate)?)))
+            .collect::<Result<Vec<(String, ValueMatcher)>, String>>()?;
+        Ok(CompiledPredicates { methods, headers, query, cookies })
+    }
+
+    ///head requests are allowed by routes that allow get
+    fn allows_method(&self, method:&Method) -> bool {
+        self.methods.is_empty() || self.methods.contains(method) || (method == Method::HEAD && self.methods.contains(&Method::GET))
+    }
+
+    fn matches_request(&self, request:&RouteRequest) -> bool {
+        self.headers.iter().all(|(header_name, matcher)| {
+            request.headers.get_all(header_name).iter().filter_map(|header_value| header_value.to_str().ok()).any(|header_value| matcher.is_match(header_value))
+        })
+        && self.query.iter().all(|(name, matcher)| {
+            request_utils::query_values(request.uri, name).iter().any(|query_value| matcher.is_match(query_value))
+        })
+        && self.cookies.iter().all(|(name, matcher)| {
+            request_utils::cookie_value(request.headers, name).is_some_and(|cookie_value| matcher.is_match(&cookie_value))
+        })
+    }
+}
+
+///returns the first of the entries whose predicates match the request
+///
+/// allowed_methods collects the methods of the entries that only failed on the method of the request
+fn first_match<'a>(entries:impl IntoIterator<Item = &'a Arc<RouteEntry>>, request:&RouteRequest, allowed_methods:&mut Vec<Method>) -> Option<Arc<RouteEntry>> {
+    for entry in entries {
+        let predicates = match &entry.predicates {
+            Some(predicates) => predicates,
+            None => return Some(entry.clone())
+        };
+        if !predicates.matches_request(request) {
+            continue;
+        }
+        if predicates.allows_method(request.method) {
+            return Some(entry.clone());
+        }
+        for method in &predicates.methods {
+            if !allowed_methods.contains(method) {
+                allowed_methods.push(method.clone());
+            }
+        }
+    }
+    None
+}
+
+///inserts the entry in the order routes are tried, by descending priority and number of predicates and then by age
+fn insert_entry(entries:&mut Vec<Arc<RouteEntry>>, entry:Arc<RouteEntry>) {
+    let sort_key = |entry:&RouteEntry| {
+        let predicates = entry.route.predicates.as_ref();
+        (
+            Reverse(predicates.map_or(0, |predicates| predicates.priority)),
+            Reverse(predicates.map_or(0, |predicates| predicates.specificity())),
+            entry.route._id
+        )
+    };
+    let index = entries.partition_point(|existing_entry| sort_key(existing_entry) <= sort_key(&entry));
+    entries.insert(index, entry);
+}
+
+///node of the segment trie, holds the routes whose address ends at this node in the order they are tried
+#[derive(Default)]
+struct RouteNode {
+    children: HashMap<String, RouteNode>,
+    param_child: Option<Box<RouteNode>>,
+    entries: Vec<Arc<RouteEntry>>,
+    catch_all_entries: Vec<Arc<RouteEntry>>
+}
+
+impl RouteNode {
+    ///returns the first route the request matches and the number of path segments matched by its address
+    ///
+    /// longer addresses are tried first, literal segments before parameters and catch-alls before the routes ending at a node
+    fn resolve(&self, segments:&[&str], depth:usize, request:&RouteRequest, allowed_methods:&mut Vec<Method>) -> Option<(Arc<RouteEntry>, usize)> {
+        if let Some(segment) = segments.get(depth) {
+            let literal_match = self.children.get(*segment).and_then(|child| child.resolve(segments, depth + 1, request, allowed_methods));
+            if literal_match.is_some() {
+                return literal_match;
+            }
+            let param_match = self.param_child.as_ref().and_then(|child| child.resolve(segments, depth + 1, request, allowed_methods));
+            if param_match.is_some() {
+                return param_match;
+            }
+        }
+        first_match(&self.catch_all_entries, request, allowed_methods)
+            .or_else(|| first_match(&self.entries, request, allowed_methods))
+            .map(|entry| (entry, depth))
+    }
+}
+
+///routes of one host, path addresses are kept in a segment trie and regex addresses are ranked against it
+#[derive(Default)]
+struct HostRoutes {
+    root: RouteNode,
+    patterns: Vec<Arc<RouteEntry>>
+}
+
+impl HostRoutes {
+    fn insert(&mut self, entry:Arc<RouteEntry>) {
+        if entry.address_pattern.is_some() {
+            return insert_entry(&mut self.patterns, entry);
+        }
+        let address_entry = entry.clone();
+        let mut node = &mut self.root;
+        for segment in address_segments(&address_entry.route.address) {
+            node = match segment {
+                AddressSegment::Literal(literal) => node.children.entry(literal.to_string()).or_default(),
+                AddressSegment::Param(_) => node.param_child.get_or_insert_with(Box::default),
+                AddressSegment::CatchAll(_) => return insert_entry(&mut node.catch_all_entries, entry)
+            };
+        }
+        insert_entry(&mut node.entries, entry);
+    }
+
+    ///a regex address competes with the segment trie on the length of the path it matched, so a `/` or `/api` prefix route
+    ///does not shadow a regex route matching more of the path, on equal length the segment address wins
+    fn resolve(&self, request:&RouteRequest, allowed_methods:&mut Vec<Method>) -> Option<(Arc<RouteEntry>, RouteParams)> {
+        let trie_match = self.resolve_segments(request, allowed_methods);
+        let pattern_match = self.resolve_patterns(request, allowed_methods);
+        match (trie_match, pattern_match) {
+            (Some((trie_entry, trie_params, trie_length)), Some((pattern_entry, pattern_params))) => {
+                if pattern_params.matched_path.len() > trie_length {
+                    Some((pattern_entry, pattern_params))
+                }else{
+                    Some((trie_entry, trie_params))
+                }
+            },
+            (Some((entry, params, _)), None) => Some((entry, params)),
+            (None, pattern_match) => pattern_match
+        }
+    }
+
+    ///returns the matched entry, its params and the length of the path it consumed, a catch-all consumes the whole path
+    fn resolve_segments(&self, request:&RouteRequest, allowed_methods:&mut Vec<Method>) -> Option<(Arc<RouteEntry>, RouteParams, usize)> {
+        let path = request.uri.path();
+        let segments: Vec<&str> = path_segments(path).collect();
+        let (entry, depth) = self.root.resolve(&segments, 0, request, allowed_methods)?;
+        let mut values = Vec::new();
+        let mut catch_all = false;
+        for (index, address_segment) in address_segments(&entry.route.address).enumerate() {
+            match address_segment {
+                AddressSegment::Param(name) => values.push((name.to_string(), segments[index].to_string())),
+                AddressSegment::CatchAll(name) => {
+                    catch_all = true;
+                    values.push((name.to_string(), segments[index..].join("/")))
+                },
+                AddressSegment::Literal(_) => ()
+            }
+        }
+        let matched_path = format!("/{}", segments[..depth].join("/"));
+        let matched_length = if catch_all { path.len() } else { matched_path.len() };
+        Some((entry, RouteParams { matched_path, values }, matched_length))
+    }
+
+    fn resolve_patterns(&self, request:&RouteRequest, allowed_methods:&mut Vec<Method>) -> Option<(Arc<RouteEntry>, RouteParams)> {
+        let path = request.uri.path();
+        let pattern_entries = self.patterns.iter().filter(|entry| entry.address_pattern.as_ref().is_some_and(|address_pattern| address_pattern.is_match(path)));
+        let entry = first_match(pattern_entries, request, allowed_methods)?;
+        let address_pattern = entry.address_pattern.as_ref()?;
+        let captures = address_pattern.captures(path)?;
+        let values = address_pattern.capture_names().flatten()
+            .filter_map(|name| Some((name.to_string(), captures.name(name)?.as_str().to_string())))
+            .collect();
+        let matched_path = captures.get(0).map_or("", |matched| matched.as_str()).to_string();
+        Some((entry, RouteParams { matched_path, values }))
+    }
+}
+
+///routes keyed by host, path addresses of each host are split into segments and kept in a trie
+///
+/// matching walks the segments of the request path once, so its cost only depends on the length of the path,
+/// the number of parameter segments and the number of routes sharing an address
+#[derive(Default)]
+pub struct RouteTable {
+    hosts: HashMap<String, HostRoutes>,
+    wildcard_hosts: HashMap<String, HostRoutes>, //keyed by the parent domain of the wildcard
+    any_host: HostRoutes,
+    entries: Vec<Arc<RouteEntry>>
+}
+
+impl RouteTable {
+    pub fn new(entries:Vec<RouteEntry>) -> RouteTable {
+        let mut route_table = RouteTable::default();
+        for entry in entries {
+            if let Some(existing_entry) = route_table.find_conflict(entry.route.host.as_deref(), &entry.route.address, entry.route.predicates.as_ref(), None) {
+                println!("[ERROR] Routes {} and {} match the same requests at {}, keeping the first", existing_entry.route._id, entry.route._id, entry.route.load_balancer_key());
+                continue;
+            }
+            let entry = Arc::new(entry);
+            let host_routes = match entry.route.host.as_deref() {
+                Some(host) => match host.strip_prefix("*.") {
+                    Some(parent) => route_table.wildcard_hosts.entry(parent.to_string()).or_default(),
+                    None => route_table.hosts.entry(host.to_string()).or_default()
+                },
+                None => &mut route_table.any_host
+            };
+            host_routes.insert(entry.clone());
+            route_table.entries.push(entry);
+        }
+        route_table
+    }
+
+    ///returns the best route for the request and the parameters captured by its address
+    ///
+    /// routes of the exact host are tried first, then routes of a wildcard host and then routes without a host,
+    /// within each the routes with the longest matching address whose predicates match win.
+    /// when no route matches but some only failed on the method the request is not allowed instead of not found
+    pub fn resolve(&self, request:&RouteRequest) -> RouteMatch {
+        let mut allowed_methods: Vec<Method> = Vec::new();
+        let host_routes = request.host.map(|host| {
+            let wildcard_routes = host.split_once('.').and_then(|(_, parent)| self.wildcard_hosts.get(parent));
+            [self.hosts.get(host), wildcard_routes]
+        }).unwrap_or_default();
+        for routes in host_routes.into_iter().flatten().chain([&self.any_host]) {
+            if let Some((entry, params)) = routes.resolve(request, &mut allowed_methods) {
+                return RouteMatch::Found(entry, params);
+            }
+        }
+        if allowed_methods.is_empty() {
+            RouteMatch::NotFound
+        }else{
+            RouteMatch::MethodNotAllowed(allowed_methods)
+        }
+    }
+
+    ///returns the route that would shadow a route with the host, address and predicates,
+    /// addresses that only differ in parameter names or a trailing catch-all match the same paths
+    pub fn find_conflict(&self, host:Option<&str>, address:&str, predicates:Option<&RoutePredicates>, excluded_route:Option<ObjectId>) -> Option<Arc<RouteEntry>> {
+        let key = address_key(address);
+        self.entries.iter()
+            .find(|entry| {
+                Some(entry.route._id) != excluded_route
+                    && entry.route.host.as_deref() == host
+                    && address_key(&entry.route.address) == key
+                    && same_predicates(entry.route.predicates.as_ref(), predicates)
+            })
+            .cloned()
+    }
+
+    pub fn route_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn routes(&self) -> impl Iterator<Item = &Route> {
+        self.entries.iter().map(|entry| &entry.route)
+    }
+}
+
+fn path_segments(path:&str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+///returns the current route table, empty until it is first loaded
+pub async fn route_table() -> Arc<RouteTable> {
+    ROUTE_TABLE.get_or_init(|| RwLock::new(Arc::new(RouteTable::default()))).read().await.clone()
+}
+
+///reads every route and the images they reference from the database
+async fn load_route_table() -> Result<RouteTable, String> {
+    let mut images: HashMap<ObjectId, String> = HashMap::new();
+    let mut image_cursor = DBCollection::IMAGES.collection::<Image>().await.find(doc!{}, None).await.map_err(|error| format!("Failed in fetching images: {}", error))?;
+    while let Some(image) = image_cursor.next().await {
+        match image {
+            Ok(image) => { images.insert(image._id, image.docker_image_id); },
+            Err(error) => println!("[ERROR] Skipped an image of the route table: {}", error)
+        }
+    }
+    let mut entries: Vec<RouteEntry> = Vec::new();
+    let mut route_cursor = DBCollection::ROUTES.collection::<Route>().await.find(doc!{}, None).await.map_err(|error| format!("Failed in fetching routes: {}", error))?;
+    while let Some(route) = route_cursor.next().await {
+        match route {
+            Ok(route) => {
+                let docker_image_id = route.mongo_image.and_then(|mongo_image| images.get(&mongo_image).cloned());
+                let route_id = route._id;
+                match RouteEntry::new(route, docker_image_id) {
+                    Ok(entry) => entries.push(entry),
+                    Err(err_string) => println!("[ERROR] Skipped route {} of the route table: {}", route_id, err_string)
+                }
+            },
+            Err(error) => println!("[ERROR] Skipped a route of the route table: {}", error)
+        }
+    }
+    Ok(RouteTable::new(entries))
+}
+
+///rebuilds the route table from the database and swaps it in, the previous table is kept when loading fails
+pub async fn refresh_route_table() {
+    match load_route_table().await {
+        Ok(new_route_table) => {
+            println!("[PROCESS] Loaded {} routes into the route table", new_route_table.route_count());
+            //clients of backend configs that were changed or removed would otherwise be kept forever
+            upstream_clients().retain_backend_clients(new_route_table.routes()).await;
+            *ROUTE_TABLE.get_or_init(|| RwLock::new(Arc::new(RouteTable::default()))).write().await = Arc::new(new_route_table);
+        },
+        Err(err_string) => println!("[ERROR] Failed in refreshing the route table: {}", err_string)
+    }
+}
+
+///background task that refreshes the route table whenever the routes collection changes
+///
+/// change streams need a replica set, on a standalone server the table is only refreshed by the route api
+pub async fn watch_route_changes() {
+    loop {
+        let mut change_stream = match DBCollection::ROUTES.collection::<Document>().await.watch(None, None).await {
+            Ok(change_stream) => change_stream,
+            Err(error) => {
+                println!("[PROCESS] Route change stream unavailable, routes are refreshed by the route api only: {}", error);
+                return;
+            }
+        };
+        //changes made between the last refresh and opening the stream are picked up here
+        refresh_route_table().await;
+        while let Some(change) = change_stream.next().await {
+            match change {
+                Ok(_) => refresh_route_table().await,
+                Err(error) => {
+                    println!("[ERROR] Route change stream closed: {}", error);
+                    break;
+                }
+            }
+        }
+        tokio::time::sleep(CHANGE_STREAM_RETRY).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(host:Option<&str>, address:&str, predicates:Option<RoutePredicates>) -> Route {
+        Route {
+            _id: ObjectId::new(),
+            mongo_image: None,
+            address: address.to_string(),
+            exposed_port: String::new(),
+            route_type: "STATIC".to_string(),
+            prefix: None,
+            upstream: None,
+            affinity: None,
+            scaling: None,
+            idle: None,
+            readiness: None,
+            health_check: None,
+            container: None,
+            body: None,
+            timeout: None,
+            backend: None,
+            retry: None,
+            circuit_breaker: None,
+            proxy_headers: None,
+            rewrite: None,
+            host: host.map(str::to_string),
+            predicates,
+            traffic_split: None
+        }
+    }
+
+    fn methods(methods:&[&str]) -> Option<RoutePredicates> {
+        Some(RoutePredicates { methods: methods.iter().map(|method| method.to_string()).collect(), ..Default::default() })
+    }
+
+    fn route_table(routes:Vec<Route>) -> RouteTable {
+        RouteTable::new(routes.into_iter().map(|route| RouteEntry::new(route, None).unwrap()).collect())
+    }
+
+    fn resolve_with(route_table:&RouteTable, host:Option<&str>, method:Method, path:&str) -> RouteMatch {
+        let uri: Uri = path.parse().unwrap();
+        let headers = HeaderMap::new();
+        route_table.resolve(&RouteRequest { host, method: &method, uri: &uri, headers: &headers })
+    }
+
+    ///returns the address of the matched route and the captured parameters
+    fn resolve(route_table:&RouteTable, host:Option<&str>, path:&str) -> Option<(String, RouteParams)> {
+        match resolve_with(route_table, host, Method::GET, path) {
+            RouteMatch::Found(entry, params) => Some((entry.route.address.clone(), params)),
+            _ => None
+        }
+    }
+
+    fn resolved_address(route_table:&RouteTable, host:Option<&str>, path:&str) -> Option<String> {
+        resolve(route_table, host, path).map(|(address, _)| address)
+    }
+
+    #[test]
+    fn longest_address_wins() {
+        let route_table = route_table(vec![route(None, "/", None), route(None, "/api", None), route(None, "/api/users", None)]);
+        assert_eq!(resolved_address(&route_table, None, "/api/users/1").as_deref(), Some("/api/users"));
+        assert_eq!(resolved_address(&route_table, None, "/api/orders").as_deref(), Some("/api"));
+        assert_eq!(resolved_address(&route_table, None, "/other").as_deref(), Some("/"));
+        assert_eq!(resolve(&route_table, None, "/api/users/1").unwrap().1.matched_path, "/api/users");
+    }
+
+    #[test]
+    fn exact_host_before_wildcard_before_any_host() {
+        let route_table = route_table(vec![route(None, "/", None), route(Some("*.example.com"), "/", None), route(Some("api.example.com"), "/", None)]);
+        let host_of = |host:&str| match resolve_with(&route_table, Some(host), Method::GET, "/") {
+            RouteMatch::Found(entry, _) => entry.route.host.clone(),
+            _ => panic!("no route for {}", host)
+        };
+        assert_eq!(host_of("api.example.com").as_deref(), Some("api.example.com"));
+        assert_eq!(host_of("www.example.com").as_deref(), Some("*.example.com"));
+        assert_eq!(host_of("example.org"), None);
+    }
+
+    #[test]
+    fn literal_segments_before_params_before_catch_all() {
+        let route_table = route_table(vec![route(None, "/files/:name", None), route(None, "/files/latest", None)]);
+        assert_eq!(resolved_address(&route_table, None, "/files/latest").as_deref(), Some("/files/latest"));
+        assert_eq!(resolved_address(&route_table, None, "/files/report").as_deref(), Some("/files/:name"));
+        //param addresses are prefixes as well
+        assert_eq!(resolved_address(&route_table, None, "/files/reports/2024").as_deref(), Some("/files/:name"));
+
+        let route_table = self::route_table(vec![route(None, "/", None), route(None, "/files/*path", None)]);
+        assert_eq!(resolved_address(&route_table, None, "/files/reports/2024").as_deref(), Some("/files/*path"));
+    }
+
+    #[test]
+    fn captures_params_and_catch_all() {
+        let route_table = route_table(vec![route(None, "/users/:id/files/*path", None)]);
+        let (_, params) = resolve(&route_table, None, "/users/42/files/docs/report.pdf").unwrap();
+        assert_eq!(params.get("id"), Some("42"));
+        assert_eq!(params.get("path"), Some("docs/report.pdf"));
+        assert_eq!(params.matched_path, "/users/42/files");
+        assert!(resolve(&route_table, None, "/users/42").is_none());
+    }
+
+    #[test]
+    fn captures_regex_groups() {
+        let route_table = route_table(vec![route(None, r"~/api/v(?<version>\d+)", None)]);
+        let (_, params) = resolve(&route_table, None, "/api/v2/users").unwrap();
+        assert_eq!(params.get("version"), Some("2"));
+        assert_eq!(params.matched_path, "/api/v2");
+        assert!(resolve(&route_table, None, "/api/latest").is_none());
+    }
+
+    #[test]
+    fn regex_addresses_rank_against_prefix_addresses() {
+        let route_table = route_table(vec![route(None, "/", None), route(None, "/api", None), route(None, "/api/v1/users", None), route(None, r"~/api/v\d+", None)]);
+        assert_eq!(resolved_address(&route_table, None, "/api/v2/orders").as_deref(), Some(r"~/api/v\d+"));
+        assert_eq!(resolved_address(&route_table, None, "/api/v1/users/1").as_deref(), Some("/api/v1/users"));
+        assert_eq!(resolved_address(&route_table, None, "/api/orders").as_deref(), Some("/api"));
+    }
+
+    #[test]
+    fn method_predicates_fall_through_and_report_allowed_methods() {
+        let route_table = route_table(vec![route(None, "/items", methods(&["GET"])), route(None, "/items", methods(&["POST", "PUT"]))]);
+        assert!(matches!(resolve_with(&route_table, None, Method::POST, "/items"), RouteMatch::Found(..)));
+        assert!(matches!(resolve_with(&route_table, None, Method::HEAD, "/items"), RouteMatch::Found(..)));
+        match resolve_with(&route_table, None, Method::DELETE, "/items") {
+            RouteMatch::MethodNotAllowed(allowed_methods) => assert_eq!(allowed_methods, vec![Method::GET, Method::POST, Method::PUT]),
+            _ => panic!("expected the method to not be allowed")
+        }
+        assert!(matches!(resolve_with(&route_table, None, Method::GET, "/other"), RouteMatch::NotFound));
+    }
+
+    #[test]
+    fn shorter_address_serves_methods_a_longer_one_rejects() {
+        let route_table = route_table(vec![route(None, "/", None), route(None, "/items", methods(&["GET"]))]);
+        match resolve_with(&route_table, None, Method::POST, "/items") {
+            RouteMatch::Found(entry, _) => assert_eq!(entry.route.address, "/"),
+            _ => panic!("expected the root route")
+        }
+    }
+
+    #[test]
+    fn priority_orders_routes_sharing_an_address() {
+        let header = |value:&str| Some(RoutePredicates {
+            headers: vec![ValuePredicate { name: "x-tenant".to_string(), value: Some(value.to_string()), pattern: None }],
+            ..Default::default()
+        });
+        let fallback = route(None, "/items", None);
+        let mut preferred = route(None, "/items", Some(RoutePredicates { priority: 1, ..methods(&["GET"]).unwrap() }));
+        preferred.prefix = Some("preferred".to_string());
+        let tenant = route(None, "/items", header("acme"));
+        let route_table = route_table(vec![fallback, tenant, preferred]);
+        match resolve_with(&route_table, None, Method::GET, "/items") {
+            RouteMatch::Found(entry, _) => assert_eq!(entry.route.prefix.as_deref(), Some("preferred")),
+            _ => panic!("expected the priority route")
+        }
+    }
+
+    #[test]
+    fn find_conflict_matches_equivalent_addresses() {
+        let existing = route(Some("example.com"), "/users/:id/*rest", None);
+        let existing_id = existing._id;
+        let route_table = route_table(vec![existing, route(None, "/items", methods(&["GET"]))]);
+        assert!(route_table.find_conflict(Some("example.com"), "/users/:user_id", None, None).is_some());
+        assert!(route_table.find_conflict(Some("example.com"), "/users/:user_id", None, Some(existing_id)).is_none());
+        assert!(route_table.find_conflict(None, "/users/:user_id", None, None).is_none());
+        assert!(route_table.find_conflict(Some("example.com"), "/users/me", None, None).is_none());
+        //predicates that only differ in priority match the same requests
+        assert!(route_table.find_conflict(None, "/items", Some(&RoutePredicates { priority: 3, ..methods(&["GET"]).unwrap() }), None).is_some());
+        assert!(route_table.find_conflict(None, "/items", methods(&["POST"]).as_ref(), None).is_none());
+    }
+
+    #[test]
+    fn conflicting_routes_keep_the_first() {
+        let first = route(None, "/items/:id", None);
+        let first_id = first._id;
+        let route_table = route_table(vec![first, route(None, "/items/:item_id", None)]);
+        assert_eq!(route_table.route_count(), 1);
+        match resolve_with(&route_table, None, Method::GET, "/items/1") {
+            RouteMatch::Found(entry, _) => assert_eq!(entry.route._id, first_id),
+            _ => panic!("expected the first route")
+        }
+    }
+
+    #[test]
+    fn expand_replaces_known_params() {
+        let params = RouteParams {
+            matched_path: "/users/42".to_string(),
+            values: vec![("id".to_string(), "42".to_string()), ("path".to_string(), "docs/a.pdf".to_string())]
+        };
+        assert_eq!(params.expand("/v2/users/{id}/{path}"), "/v2/users/42/docs/a.pdf");
+        assert_eq!(params.expand("/users/{unknown}/{id"), "/users/{unknown}/{id");
+        assert_eq!(params.expand("/users/${id}/{id}"), "/users/${id}/42");
+    }
+}
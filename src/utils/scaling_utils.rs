@@ -0,0 +1,262 @@
+use std::{env, time::{Duration, Instant, UNIX_EPOCH}};
+
+use mongodb::bson::{doc, oid::ObjectId};
+
+use crate::models::{docker_models::{self, IdleAction, IdleConfig, Route, ScalingConfig}, load_balancer_models::{ActiveServiceDirectory, LOAD_BALANCERS}};
+
+use super::{docker_utils::{self, create_container_instance_by_load_balancer_key, try_start_container}, mongodb_utils::DBCollection};
+
+#[derive(Debug, PartialEq)]
+pub enum ScalingDecision {
+    Up,
+    Down,
+    Hold
+}
+
+///decides whether a load balancer needs a container more or less
+///
+/// replica bounds are enforced regardless of cooldowns,
+/// scaling down checks the load the remaining containers would carry
+pub fn scaling_decision(scaling:&ScalingConfig, container_count:usize, total_in_flight:usize, since_last_scale:Option<Duration>) -> ScalingDecision {
+    if container_count < scaling.min_replicas {
+        return ScalingDecision::Up;
+    }
+    if container_count > scaling.max_replicas {
+        return ScalingDecision::Down;
+    }
+    let is_cooled_down = |cooldown:u64| since_last_scale.is_none_or(|since_last_scale| since_last_scale >= Duration::from_secs(cooldown));
+    let average_in_flight = total_in_flight as f64 / container_count.max(1) as f64;
+    if container_count < scaling.max_replicas && average_in_flight > scaling.scale_up_in_flight && is_cooled_down(scaling.scale_up_cooldown) {
+        return ScalingDecision::Up;
+    }
+    if container_count > scaling.min_replicas && container_count > 1 && is_cooled_down(scaling.scale_down_cooldown) {
+        let remaining_average_in_flight = total_in_flight as f64 / (container_count - 1) as f64;
+        if remaining_average_in_flight < scaling.scale_down_in_flight {
+            return ScalingDecision::Down;
+        }
+    }
+    ScalingDecision::Hold
+}
+
+///background task that keeps every load balancer with automatic container instancing within its scaling config
+///
+/// runs every AUTOSCALE_INTERVAL seconds, defaults to 5
+pub async fn autoscale(){
+    let interval_seconds = env::var("AUTOSCALE_INTERVAL").ok().and_then(|interval| interval.parse::<u64>().ok()).unwrap_or(5);
+    let mut interval = tokio::time::interval(Duration::from_secs(interval_seconds));
+    loop {
+        interval.tick().await;
+        for load_balancer_key in autoscaled_load_balancer_keys().await {
+            autoscale_load_balancer(&load_balancer_key).await;
+        }
+    }
+}
+
+async fn autoscaled_load_balancer_keys() -> Vec<String> {
+    let load_balancers_mutex = match LOAD_BALANCERS.get() {
+        Some(load_balancers_mutex) => load_balancers_mutex.lock().await,
+        None => return vec![]
+    };
+    let mut load_balancer_keys = Vec::new();
+    for (load_balancer_key, load_balancer) in load_balancers_mutex.iter() {
+        if *load_balancer.automatic_container_instancing.lock().await && *load_balancer.validated.lock().await {
+            load_balancer_keys.push(load_balancer_key.clone());
+        }
+    }
+    load_balancer_keys
+}
+
+async fn autoscale_load_balancer(load_balancer_key:&String){
+    let (scaling, since_last_scale) = {
+        let load_balancers_mutex = LOAD_BALANCERS.get().unwrap().lock().await;
+        let load_balancer = match load_balancers_mutex.get(load_balancer_key) {
+            Some(load_balancer) => load_balancer,
+            None => return
+        };
+        let scaling = match load_balancer.scaling.lock().await.clone() {
+            Some(scaling) => scaling,
+            None => return
+        };
+        let since_last_scale = load_balancer.last_scaled_at.lock().await.map(|last_scaled_at| last_scaled_at.elapsed());
+        (scaling, since_last_scale)
+    };
+    //the route can be removed since the load balancer keys were collected
+    let containers = match ActiveServiceDirectory::get_load_balancer_containers(load_balancer_key).await {
+        Some(containers) => containers,
+        None => return
+    };
+    let container_loads = ActiveServiceDirectory::get_container_loads(&containers).await;
+    let total_in_flight: usize = container_loads.iter().map(|container_load| container_load.in_flight).sum();
+
+    match scaling_decision(&scaling, containers.len(), total_in_flight, since_last_scale) {
+        ScalingDecision::Up => {
+            println!("[PROCESS] Scaling up {} from {} containers ({} in-flight)", load_balancer_key, containers.len(), total_in_flight);
+            match create_container_instance_by_load_balancer_key(load_balancer_key).await {
+                Ok(container) => {
+                    if let Err(err_string) = try_start_container(&container.container_id).await {
+                        println!("[ERROR] {}", err_string);
+                    }
+                },
+                Err(err_string) => println!("[ERROR] Failed to scale up {}: {}", load_balancer_key, err_string)
+            }
+        },
+        ScalingDecision::Down => {
+            //only idle containers are retired so no request is cut off
+            let idle_container = container_loads.iter().rev().find(|container_load| container_load.in_flight == 0);
+            match idle_container {
+                Some(container_load) => {
+                    println!("[PROCESS] Scaling down {} from {} containers ({} in-flight)", load_balancer_key, containers.len(), total_in_flight);
+                    ActiveServiceDirectory::retire_container(&container_load.container_id, load_balancer_key).await;
+                },
+                None => return
+            }
+        },
+        ScalingDecision::Hold => return
+    }
+    if let Some(load_balancer) = LOAD_BALANCERS.get().unwrap().lock().await.get(load_balancer_key) {
+        *load_balancer.last_scaled_at.lock().await = Some(Instant::now());
+    }
+}
+
+///background task that stops or removes containers of routes with an idle config once they are idle past the timeout
+///
+/// runs every IDLE_REAP_INTERVAL seconds, defaults to 30.
+/// containers with in-flight requests are never reaped and removal keeps the scaling min_replicas
+pub async fn reap_idle_containers(){
+    let interval_seconds = env::var("IDLE_REAP_INTERVAL").ok().and_then(|interval| interval.parse::<u64>().ok()).unwrap_or(30);
+    let mut interval = tokio::time::interval(Duration::from_secs(interval_seconds));
+    loop {
+        interval.tick().await;
+        let mut cursor = match DBCollection::ROUTES.collection::<Route>().await.find(doc!{
+            "idle": { "$ne": null },
+            "mongo_image": { "$ne": null }
+        }, None).await {
+            Ok(cursor) => cursor,
+            Err(_) => {
+                println!("[ERROR] Cannot fetch routes to reap");
+                continue;
+            }
+        };
+        let mut routes: Vec<Route> = Vec::new();
+        while let Ok(true) = cursor.advance().await {
+            if let Ok(route) = cursor.deserialize_current() {
+                routes.push(route);
+            }
+        }
+        for route in routes.iter() {
+            reap_route_containers(route).await;
+        }
+    }
+}
+
+async fn reap_route_containers(route:&Route){
+    let idle = match &route.idle {
+        Some(idle) => idle,
+        None => return
+    };
+    for (variant, mongo_image) in route.variant_images() {
+        reap_variant_containers(route, idle, &variant, mongo_image).await;
+    }
+}
+
+///reaps the idle containers of the image of the route or of one of its variants
+async fn reap_variant_containers(route:&Route, idle:&IdleConfig, variant:&str, mongo_image:ObjectId){
+    let mongo_load_balancer = match DBCollection::LOADBALANCERS.collection::<docker_models::LoadBalancer>().await.find_one(doc!{
+        "mongo_image_reference": mongo_image
+    }, None).await {
+        Ok(Some(mongo_load_balancer)) => mongo_load_balancer,
+        _ => return
+    };
+    let current_time = std::time::SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    let min_replicas = route.scaling.as_ref().map_or(0, |scaling| scaling.min_replicas);
+    let load_balancer_key = ActiveServiceDirectory::get_load_balancer_key(route.variant_load_balancer_key(variant)).await;
+    let container_loads = ActiveServiceDirectory::get_container_loads(&mongo_load_balancer.containers).await;
+    let mut container_count = mongo_load_balancer.containers.len();
+
+    for container_load in container_loads.iter() {
+        if container_load.in_flight > 0 {
+            continue;
+        }
+        let container = match DBCollection::CONTAINERS.collection::<docker_models::Container>().await.find_one(doc!{
+            "container_id": &container_load.container_id
+        }, None).await {
+            Ok(Some(container)) => container,
+            _ => continue
+        };
+        //containers that never started have no activity to measure
+        let last_activity = match container.time_requested.max(container.time_responded) {
+            Some(last_activity) => last_activity,
+            None => continue
+        };
+        if current_time - last_activity < idle.timeout as i64 {
+            continue;
+        }
+        match idle.action {
+            IdleAction::Stop => {
+                if docker_utils::is_container_running(&container.container_id).await {
+                    println!("[PROCESS] Stopping container {} of {} idle for {}s", &container.container_id, &route.address, current_time - last_activity);
+                    docker_utils::stop_docker_container(&container.container_id).await;
+                }
+            },
+            IdleAction::Remove => {
+                if container_count <= min_replicas {
+                    continue;
+                }
+                println!("[PROCESS] Removing container {} of {} idle for {}s", &container.container_id, &route.address, current_time - last_activity);
+                match &load_balancer_key {
+                    Some(load_balancer_key) => ActiveServiceDirectory::retire_container(&container.container_id, load_balancer_key).await,
+                    None => {
+                        docker_utils::remove_container_record(&mongo_load_balancer._id, &container.container_id).await;
+                        docker_utils::remove_docker_container(&container.container_id).await;
+                    }
+                }
+                container_count -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scaling_config() -> ScalingConfig {
+        ScalingConfig { min_replicas: 2, max_replicas: 4, scale_up_in_flight: 10.0, scale_down_in_flight: 2.0, scale_up_cooldown: 30, scale_down_cooldown: 60 }
+    }
+
+    #[test]
+    fn replica_bounds_ignore_cooldowns() {
+        let scaling = scaling_config();
+        let just_scaled = Some(Duration::ZERO);
+        assert_eq!(scaling_decision(&scaling, 1, 0, just_scaled), ScalingDecision::Up);
+        assert_eq!(scaling_decision(&scaling, 5, 100, just_scaled), ScalingDecision::Down);
+    }
+
+    #[test]
+    fn scales_up_above_in_flight_threshold() {
+        let scaling = scaling_config();
+        assert_eq!(scaling_decision(&scaling, 2, 30, None), ScalingDecision::Up);
+        assert_eq!(scaling_decision(&scaling, 2, 20, None), ScalingDecision::Hold);
+        //max_replicas caps scaling up
+        assert_eq!(scaling_decision(&scaling, 4, 100, None), ScalingDecision::Hold);
+    }
+
+    #[test]
+    fn scales_down_when_remaining_containers_stay_below_threshold() {
+        let scaling = scaling_config();
+        assert_eq!(scaling_decision(&scaling, 3, 2, None), ScalingDecision::Down);
+        //two containers would carry 2.5 requests each
+        assert_eq!(scaling_decision(&scaling, 3, 5, None), ScalingDecision::Hold);
+        //min_replicas caps scaling down
+        assert_eq!(scaling_decision(&scaling, 2, 0, None), ScalingDecision::Hold);
+    }
+
+    #[test]
+    fn cooldowns_hold_scaling() {
+        let scaling = scaling_config();
+        assert_eq!(scaling_decision(&scaling, 2, 30, Some(Duration::from_secs(10))), ScalingDecision::Hold);
+        assert_eq!(scaling_decision(&scaling, 2, 30, Some(Duration::from_secs(30))), ScalingDecision::Up);
+        assert_eq!(scaling_decision(&scaling, 3, 0, Some(Duration::from_secs(30))), ScalingDecision::Hold);
+        assert_eq!(scaling_decision(&scaling, 3, 0, Some(Duration::from_secs(60))), ScalingDecision::Down);
+    }
+}
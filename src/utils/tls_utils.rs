@@ -0,0 +1,116 @@
+use std::{collections::HashMap, fs, io::{self, BufReader}, path::{Path, PathBuf}, sync::Arc};
+
+use axum::{middleware::AddExtension, Extension};
+use axum_server::{accept::Accept, tls_rustls::{RustlsAcceptor, RustlsConfig}};
+use futures_util::future::BoxFuture;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::{rustls::{server::{ClientHello, ResolvesServerCert}, sign::{self, CertifiedKey}, Certificate, PrivateKey, ServerConfig}, server::TlsStream};
+use tower_layer::Layer;
+
+use super::request_utils;
+
+const KEYS_DIRECTORY: &str = "./src/keys";
+
+///server name the client sent during the tls handshake, normalized like a host header
+#[derive(Clone, Debug)]
+pub struct TlsServerName(pub Option<String>);
+
+///picks the certificate of the sni server name, exact hosts before wildcards, and falls back to the default certificate
+struct SniCertResolver {
+    default_cert: Arc<CertifiedKey>,
+    host_certs: HashMap<String, Arc<CertifiedKey>>
+}
+
+impl ResolvesServerCert for SniCertResolver {
+    fn resolve(&self, client_hello:ClientHello) -> Option<Arc<CertifiedKey>> {
+        let host_cert = client_hello.server_name().map(request_utils::normalize_host).and_then(|server_name| {
+            self.host_certs.get(&server_name).or_else(|| {
+                let (_, parent) = server_name.split_once('.')?;
+                self.host_certs.get(&format!("*.{}", parent))
+            })
+        });
+        Some(host_cert.unwrap_or(&self.default_cert).clone())
+    }
+}
+
+fn load_certified_key(cert_path:&Path, key_path:&Path) -> Result<CertifiedKey, String> {
+    let cert_file = fs::File::open(cert_path).map_err(|error| format!("Cannot read {}: {}", cert_path.display(), error))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+        .map(|cert| cert.map(|cert| Certificate(cert.to_vec())))
+        .collect::<Result<Vec<Certificate>, io::Error>>()
+        .map_err(|error| format!("{} is not a pem certificate: {}", cert_path.display(), error))?;
+    let key_file = fs::File::open(key_path).map_err(|error| format!("Cannot read {}: {}", key_path.display(), error))?;
+    let key = match rustls_pemfile::private_key(&mut BufReader::new(key_file)) {
+        Ok(Some(key)) => PrivateKey(key.secret_der().to_vec()),
+        _ => return Err(format!("{} has no pem private key", key_path.display()))
+    };
+    let signing_key = sign::any_supported_type(&key).map_err(|error| format!("{} is not a supported private key: {}", key_path.display(), error))?;
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+///loads the default certificate and the host certificates of the keys/hosts directory, selected by the sni of each connection
+///
+/// host certificates are named <host>.crt and <host>_pem.pem like the default certificate,
+/// wildcard certificates use _wildcard.<parent> as the host
+pub fn rustls_config() -> Result<RustlsConfig, String> {
+    let keys_directory = PathBuf::from(KEYS_DIRECTORY);
+    let default_cert = load_certified_key(&keys_directory.join("orchestrator.crt"), &keys_directory.join("orchestrator_pem.pem"))?;
+    let mut host_certs = HashMap::new();
+    if let Ok(host_entries) = fs::read_dir(keys_directory.join("hosts")) {
+        for host_entry in host_entries.flatten() {
+            let cert_path = host_entry.path();
+            let host = match cert_path.file_name().and_then(|file_name| file_name.to_str()).and_then(|file_name| file_name.strip_suffix(".crt")) {
+                Some(host) => host.to_string(),
+                None => continue
+            };
+            let host_pattern = match host.strip_prefix("_wildcard.") {
+                Some(parent) => format!("*.{}", parent),
+                None => host.clone()
+            }.to_ascii_lowercase();
+            match load_certified_key(&cert_path, &cert_path.with_file_name(format!("{}_pem.pem", host))) {
+                Ok(host_cert) => {
+                    println!("[PROCESS] Loaded the certificate of {}", &host_pattern);
+                    host_certs.insert(host_pattern, Arc::new(host_cert));
+                },
+                Err(err_string) => println!("[ERROR] Skipped the certificate of {}: {}", &host_pattern, err_string)
+            }
+        }
+    }
+    let mut server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(SniCertResolver { default_cert: Arc::new(default_cert), host_certs }));
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(RustlsConfig::from_config(Arc::new(server_config)))
+}
+
+///rustls acceptor that adds the sni server name of each connection to its requests as a [type TlsServerName] extension
+#[derive(Clone)]
+pub struct SniAcceptor {
+    inner: RustlsAcceptor
+}
+
+impl SniAcceptor {
+    pub fn new(config:RustlsConfig) -> SniAcceptor {
+        SniAcceptor { inner: RustlsAcceptor::new(config) }
+    }
+}
+
+impl<I, S> Accept<I, S> for SniAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static
+{
+    type Stream = TlsStream<I>;
+    type Service = AddExtension<S, TlsServerName>;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream:I, service:S) -> Self::Future {
+        let acceptor = self.inner.clone();
+        Box::pin(async move {
+            let (stream, service) = acceptor.accept(stream, service).await?;
+            let server_name = TlsServerName(stream.get_ref().1.server_name().map(request_utils::normalize_host));
+            Ok((stream, Extension(server_name).layer(service)))
+        })
+    }
+}
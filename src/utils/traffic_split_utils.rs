@@ -0,0 +1,48 @@
+use hyper::HeaderMap;
+use mongodb::bson::oid::ObjectId;
+use rand::Rng;
+
+use crate::models::docker_models::{Route, PRIMARY_VARIANT};
+
+use super::{load_balancer_utils::hash_key, request_utils};
+
+///returns the name of the variant pinned by the pin header or cookie of the traffic split
+fn pinned_variant(route:&Route, headers:&HeaderMap) -> Option<String> {
+    let traffic_split = route.traffic_split.as_ref()?;
+    let pin_header = traffic_split.pin_header.as_deref()
+        .and_then(|pin_header| headers.get(pin_header))
+        .and_then(|header_value| header_value.to_str().ok())
+        .map(String::from);
+    pin_header.or_else(|| traffic_split.pin_cookie.as_deref().and_then(|pin_cookie| request_utils::cookie_value(headers, pin_cookie)))
+}
+
+///returns the name and image of the variant of a container route that serves the request
+/// 
+/// a pinned variant serves the request regardless of the weights, otherwise the affinity key keeps a client on the same variant
+/// while the weight of the variant grows and requests without one are split at random
+pub fn select_variant(route:&Route, mongo_image:ObjectId, headers:&HeaderMap, affinity_key:Option<&str>) -> (String, ObjectId) {
+    let traffic_split = match &route.traffic_split {
+        Some(traffic_split) => traffic_split,
+        None => return (PRIMARY_VARIANT.to_string(), mongo_image)
+    };
+    if let Some(pinned_variant) = pinned_variant(route, headers) {
+        if pinned_variant == PRIMARY_VARIANT {
+            return (PRIMARY_VARIANT.to_string(), mongo_image);
+        }
+        if let Some(variant) = traffic_split.variants.iter().find(|variant| variant.name == pinned_variant) {
+            return (variant.name.clone(), variant.mongo_image);
+        }
+    }
+    let split_point = match affinity_key {
+        Some(affinity_key) => (hash_key(affinity_key) % 100) as u32,
+        None => rand::thread_rng().gen_range(0..100)
+    };
+    let mut cumulative_weight = 0;
+    for variant in traffic_split.variants.iter() {
+        cumulative_weight += variant.weight;
+        if split_point < cumulative_weight {
+            return (variant.name.clone(), variant.mongo_image);
+        }
+    }
+    (PRIMARY_VARIANT.to_string(), mongo_image)
+}
@@ -0,0 +1,288 @@
+use std::{collections::{HashMap, HashSet}, env, fs, net::{IpAddr, SocketAddr}, sync::{Arc, OnceLock}, time::Duration};
+
+use ring::digest::{digest, SHA256};
+use rustls::{client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier}, crypto::{ring::default_provider, verify_tls12_signature, verify_tls13_signature, CryptoProvider}, pki_types::{CertificateDer, ServerName, UnixTime}, CertificateError, DigitallySignedStruct, SignatureScheme};
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::models::docker_models::{BackendConfig, BackendScheme, Route};
+
+///keep-alive and pooling settings of the upstream clients
+///
+/// pool_idle_timeout, tcp_keepalive and http2_keep_alive_interval are in seconds.
+/// https destinations that offer h2 are multiplexed over a single pooled connection
+#[derive(Clone, Debug, Serialize)]
+pub struct UpstreamPoolConfig {
+    pub pool_idle_timeout: u64,
+    pub pool_max_idle_per_host: usize,
+    pub tcp_keepalive: u64,
+    pub http2_keep_alive_interval: Option<u64>
+}
+
+impl UpstreamPoolConfig {
+    ///reads UPSTREAM_POOL_IDLE_TIMEOUT, UPSTREAM_POOL_MAX_IDLE_PER_HOST, UPSTREAM_TCP_KEEPALIVE and UPSTREAM_HTTP2_KEEP_ALIVE_INTERVAL
+    pub fn from_env() -> UpstreamPoolConfig {
+        let parse_env = |name:&str| env::var(name).ok().and_then(|value| value.parse::<u64>().ok());
+        UpstreamPoolConfig {
+            pool_idle_timeout: parse_env("UPSTREAM_POOL_IDLE_TIMEOUT").unwrap_or(90),
+            pool_max_idle_per_host: parse_env("UPSTREAM_POOL_MAX_IDLE_PER_HOST").map_or(32, |max_idle| max_idle as usize),
+            tcp_keepalive: parse_env("UPSTREAM_TCP_KEEPALIVE").unwrap_or(60),
+            http2_keep_alive_interval: parse_env("UPSTREAM_HTTP2_KEEP_ALIVE_INTERVAL")
+        }
+    }
+
+    fn client_builder(&self) -> reqwest::ClientBuilder {
+        let client_builder = reqwest::ClientBuilder::new()
+            .use_rustls_tls()
+            .danger_accept_invalid_certs(true)
+            .pool_idle_timeout(Duration::from_secs(self.pool_idle_timeout))
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .tcp_keepalive(Duration::from_secs(self.tcp_keepalive))
+            .http2_adaptive_window(true);
+        match self.http2_keep_alive_interval {
+            Some(interval) => client_builder.http2_keep_alive_interval(Duration::from_secs(interval)).http2_keep_alive_while_idle(true),
+            None => client_builder
+        }
+    }
+
+    ///returns a client builder that applies the scheme and tls settings of the backend config
+    fn backend_client_builder(&self, backend:&BackendConfig, upgrade:bool) -> Result<reqwest::ClientBuilder, String> {
+        let mut client_builder = match (backend.scheme, upgrade) {
+            (_, true) => self.client_builder().http1_only(),
+            (BackendScheme::H2c, false) => self.client_builder().http2_prior_knowledge(),
+            _ => self.client_builder()
+        };
+        if backend.scheme != BackendScheme::Https {
+            return Ok(client_builder);
+        }
+        if let Some(pinned_cert) = &backend.pinned_cert {
+            return Ok(client_builder.use_preconfigured_tls(pinned_tls_config(pinned_cert, backend.client_cert.as_deref(), upgrade)?));
+        }
+        client_builder = client_builder.danger_accept_invalid_certs(!backend.verify && backend.ca_bundle.is_none());
+        if let Some(ca_bundle) = &backend.ca_bundle {
+            let certificates = reqwest::Certificate::from_pem_bundle(&read_pem(ca_bundle)?).map_err(|_| format!("ca_bundle {} is not a valid pem bundle", ca_bundle))?;
+            for certificate in certificates {
+                client_builder = client_builder.add_root_certificate(certificate);
+            }
+        }
+        if let Some(client_cert) = &backend.client_cert {
+            let identity = reqwest::Identity::from_pem(&read_pem(client_cert)?).map_err(|_| format!("client_cert {} does not hold a certificate and private key", client_cert))?;
+            client_builder = client_builder.identity(identity);
+        }
+        Ok(client_builder)
+    }
+}
+
+fn read_pem(path:&str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|error| format!("Cannot read {}: {}", path, error))
+}
+
+///returns the sha256 fingerprint of a hex string, colons between bytes are allowed
+fn parse_fingerprint(pinned_cert:&str) -> Result<Vec<u8>, String> {
+    let hex_fingerprint = pinned_cert.replace(':', "");
+    if hex_fingerprint.len() != 64 || !hex_fingerprint.is_ascii() {
+        return Err(format!("pinned_cert {} is not a hex sha256 fingerprint", pinned_cert));
+    }
+    (0..hex_fingerprint.len()).step_by(2)
+        .map(|index| u8::from_str_radix(&hex_fingerprint[index..index + 2], 16).map_err(|_| format!("pinned_cert {} is not a hex sha256 fingerprint", pinned_cert)))
+        .collect()
+}
+
+///accepts only the certificate matching the pinned fingerprint, signatures are still checked against it
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    fingerprint: Vec<u8>,
+    provider: CryptoProvider
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(&self, end_entity:&CertificateDer<'_>, _intermediates:&[CertificateDer<'_>], _server_name:&ServerName<'_>, _ocsp_response:&[u8], _now:UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+        if digest(&SHA256, end_entity.as_ref()).as_ref() == self.fingerprint.as_slice() {
+            Ok(ServerCertVerified::assertion())
+        }else{
+            Err(rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure))
+        }
+    }
+
+    fn verify_tls12_signature(&self, message:&[u8], cert:&CertificateDer<'_>, dss:&DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message:&[u8], cert:&CertificateDer<'_>, dss:&DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+///returns the tls config of a pinned destination, alpn is set here since reqwest leaves preconfigured tls as is
+fn pinned_tls_config(pinned_cert:&str, client_cert:Option<&str>, upgrade:bool) -> Result<rustls::ClientConfig, String> {
+    let verifier = PinnedCertVerifier { fingerprint: parse_fingerprint(pinned_cert)?, provider: default_provider() };
+    let config_builder = rustls::ClientConfig::builder().dangerous().with_custom_certificate_verifier(Arc::new(verifier));
+    let mut tls_config = match client_cert {
+        Some(client_cert) => {
+            let pem = read_pem(client_cert)?;
+            let cert_chain = rustls_pemfile::certs(&mut pem.as_slice()).collect::<Result<Vec<_>, _>>().map_err(|_| format!("client_cert {} is not a valid pem file", client_cert))?;
+            let private_key = match rustls_pemfile::private_key(&mut pem.as_slice()) {
+                Ok(Some(private_key)) => private_key,
+                _ => return Err(format!("client_cert {} does not hold a private key", client_cert))
+            };
+            config_builder.with_client_auth_cert(cert_chain, private_key).map_err(|error| format!("client_cert {} is invalid: {}", client_cert, error))?
+        },
+        None => config_builder.with_no_client_auth()
+    };
+    tls_config.alpn_protocols = if upgrade { vec![b"http/1.1".to_vec()] } else { vec![b"h2".to_vec(), b"http/1.1".to_vec()] };
+    Ok(tls_config)
+}
+
+///returns true when the backend config cannot be served by the shared clients
+fn requires_backend_client(backend:&BackendConfig) -> bool {
+    backend.scheme == BackendScheme::H2c || backend.verify || backend.ca_bundle.is_some() || backend.pinned_cert.is_some() || backend.server_name.is_some() || backend.client_cert.is_some()
+}
+
+///returns the base url with its host replaced by the server_name and the address the server_name resolves to
+async fn server_name_upstream(upstream:&str, server_name:&str) -> Result<(String, SocketAddr), String> {
+    let mut upstream_url = reqwest::Url::parse(upstream).map_err(|_| format!("{} is not a valid upstream", upstream))?;
+    let (host, port) = match (upstream_url.host_str(), upstream_url.port_or_known_default()) {
+        (Some(host), Some(port)) => (host.trim_start_matches('[').trim_end_matches(']').to_string(), port),
+        _ => return Err(format!("{} has no host and port", upstream))
+    };
+    let address = match host.parse::<IpAddr>() {
+        Ok(ip_address) => SocketAddr::new(ip_address, port),
+        Err(_) => tokio::net::lookup_host((host.as_str(), port)).await.ok().and_then(|mut addresses| addresses.next())
+            .ok_or_else(|| format!("Cannot resolve {}", host))?
+    };
+    upstream_url.set_host(Some(server_name)).map_err(|_| format!("server_name {} is not a valid host", server_name))?;
+    Ok((upstream_url.as_str().trim_end_matches('/').to_string(), address))
+}
+
+///clients of a backend config keyed by upgrade and the address its server_name resolved to
+type BackendClients = HashMap<(bool, Option<SocketAddr>), reqwest::Client>;
+
+fn backend_client_key(backend:&BackendConfig) -> String {
+    serde_json::to_string(backend).unwrap_or_default()
+}
+
+///checks that a client can be built from the backend config, reading its certificate files
+pub fn validate_backend(backend:&BackendConfig) -> Result<(), String> {
+    if let Some(server_name) = &backend.server_name {
+        reqwest::Url::parse(&format!("https://{}", server_name)).map_err(|_| format!("server_name {} is not a valid host", server_name))?;
+    }
+    UpstreamPoolConfig::from_env().backend_client_builder(backend, false)?.build().map(|_| ()).map_err(|error| format!("Cannot build a client for the backend: {}", error))
+}
+
+///usage of the pooled connections to a destination
+///
+/// requests and connect_errors are totals, in_flight and upgraded_connections are currently open
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct UpstreamStats {
+    pub requests: u64,
+    pub in_flight: usize,
+    pub connect_errors: u64,
+    pub upgraded_connections: usize
+}
+
+///long-lived clients every forwarded request goes through so connections to the destinations are reused
+///
+/// upgrade_client only speaks http/1.1 since upgrades are not defined for h2,
+/// backend_clients are the clients of routes with their own scheme or tls settings keyed by the serialized backend config,
+/// then by upgrade and the address a server_name resolved to
+pub struct UpstreamClients {
+    pub client: reqwest::Client,
+    pub upgrade_client: reqwest::Client,
+    pub config: UpstreamPoolConfig,
+    backend_clients: Mutex<HashMap<String, BackendClients>>,
+    stats: Mutex<HashMap<String, UpstreamStats>>
+}
+
+pub static UPSTREAM_CLIENTS:OnceLock<UpstreamClients> = OnceLock::new();
+
+///returns the shared upstream clients, built from the environment on first use
+pub fn upstream_clients() -> &'static UpstreamClients {
+    UPSTREAM_CLIENTS.get_or_init(|| {
+        let config = UpstreamPoolConfig::from_env();
+        UpstreamClients {
+            client: config.client_builder().build().unwrap(),
+            upgrade_client: config.client_builder().http1_only().build().unwrap(),
+            config,
+            backend_clients: Mutex::new(HashMap::new()),
+            stats: Mutex::new(HashMap::new())
+        }
+    })
+}
+
+impl UpstreamClients {
+    ///returns the client and base url of a request to the upstream base url under the backend config of a route
+    ///
+    /// routes without tls settings share the pooled clients, others share a pooled client per backend config.
+    /// a server_name replaces the host of the base url and is resolved to the address of the upstream
+    pub async fn backend_client(&self, backend:Option<&BackendConfig>, upstream:&str, upgrade:bool) -> Result<(reqwest::Client, String), String> {
+        let backend = match backend {
+            Some(backend) if requires_backend_client(backend) => backend,
+            _ => return Ok((if upgrade { self.upgrade_client.clone() } else { self.client.clone() }, upstream.to_string()))
+        };
+        let (base_url, resolved_address) = match &backend.server_name {
+            Some(server_name) => {
+                let (base_url, address) = server_name_upstream(upstream, server_name).await?;
+                (base_url, Some(address))
+            },
+            None => (upstream.to_string(), None)
+        };
+        let mut backend_clients_mutex = self.backend_clients.lock().await;
+        let backend_clients = backend_clients_mutex.entry(backend_client_key(backend)).or_default();
+        if let Some(client) = backend_clients.get(&(upgrade, resolved_address)) {
+            return Ok((client.clone(), base_url));
+        }
+        let mut client_builder = self.config.backend_client_builder(backend, upgrade)?;
+        if let (Some(server_name), Some(address)) = (&backend.server_name, resolved_address) {
+            client_builder = client_builder.resolve(server_name, address);
+        }
+        let client = client_builder.build().map_err(|error| format!("Cannot build a client for {}: {}", upstream, error))?;
+        backend_clients.insert((upgrade, resolved_address), client.clone());
+        Ok((client, base_url))
+    }
+
+    ///drops the clients of backend configs none of the routes use anymore, their pooled connections are closed
+    pub async fn retain_backend_clients<'a>(&self, routes:impl Iterator<Item = &'a Route>) {
+        let backend_client_keys: HashSet<String> = routes.filter_map(|route| route.backend.as_ref()).map(backend_client_key).collect();
+        self.backend_clients.lock().await.retain(|backend_client_key, _| backend_client_keys.contains(backend_client_key));
+    }
+
+    ///counts a request to the upstream base url as in-flight
+    pub async fn begin_request(&self, upstream:&str) {
+        let mut stats_mutex = self.stats.lock().await;
+        let stats = stats_mutex.entry(upstream.to_string()).or_default();
+        stats.requests += 1;
+        stats.in_flight += 1;
+    }
+
+    pub async fn end_request(&self, upstream:&str) {
+        if let Some(stats) = self.stats.lock().await.get_mut(upstream) {
+            stats.in_flight = stats.in_flight.saturating_sub(1);
+        }
+    }
+
+    pub async fn record_connect_error(&self, upstream:&str) {
+        self.stats.lock().await.entry(upstream.to_string()).or_default().connect_errors += 1;
+    }
+
+    ///tracks an upgraded connection that left the pool, opened is false once it closes
+    pub async fn record_upgraded_connection(&self, upstream:&str, opened:bool) {
+        let mut stats_mutex = self.stats.lock().await;
+        let stats = stats_mutex.entry(upstream.to_string()).or_default();
+        stats.upgraded_connections = if opened { stats.upgraded_connections + 1 } else { stats.upgraded_connections.saturating_sub(1) };
+    }
+
+    ///drops the stats of the destination at the host:port address, used once the container behind it is removed
+    /// so its port can be handed to a new container without inheriting them
+    pub async fn remove_upstream_stats(&self, address:&str) {
+        self.stats.lock().await.retain(|upstream, _| upstream.split_once("://").map_or(upstream.as_str(), |(_, upstream_address)| upstream_address) != address);
+    }
+
+    ///returns the stats of every destination keyed by upstream base url
+    pub async fn stats(&self) -> HashMap<String, UpstreamStats> {
+        self.stats.lock().await.clone()
+    }
+}